@@ -0,0 +1,81 @@
+// src/config_file.rs
+//! Defaults from `smart-organizer.toml`, looked up in the current folder and
+//! then in the config folder. Command-line flags and env variables win.
+
+use serde::Deserialize;
+use smart_file_organizer::profiles::profiles_dir;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE_NAME: &str = "smart-organizer.toml";
+
+/// Keys of the config file; all optional. Enum values are spelled as on the
+/// command line (`on_conflict = "skip"`).
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub src: Option<PathBuf>,
+    pub dst: Option<PathBuf>,
+    pub dry_run: Option<bool>,
+    pub overwrite: Option<bool>,
+    pub on_conflict: Option<String>,
+    pub conflict_template: Option<String>,
+    pub rules: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub history: Option<PathBuf>,
+    pub history_limit: Option<usize>,
+    /// Age such as "30d", as for `--history-max-age`
+    pub history_max_age: Option<String>,
+    pub include_hidden: Option<bool>,
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    pub respect_gitignore: Option<bool>,
+    /// Sizes such as "10MB", as for `--min-size`/`--max-size`
+    pub min_size: Option<String>,
+    pub max_size: Option<String>,
+    pub skip_empty: Option<bool>,
+    /// Ages such as "7d", as for `--newer-than`/`--older-than`
+    pub newer_than: Option<String>,
+    pub older_than: Option<String>,
+    /// Age such as "10s", as for `--min-age`
+    pub min_age: Option<String>,
+    pub partial_extensions: Option<Vec<String>>,
+    pub groups: Option<Vec<String>>,
+    pub max_depth: Option<usize>,
+    pub copy: Option<bool>,
+    /// "hard" or "symbolic", as for `--link`
+    pub link: Option<String>,
+    pub verify: Option<bool>,
+    pub trash: Option<bool>,
+    /// "nfc" or "nfd", as for `--normalize-unicode`
+    pub normalize_unicode: Option<String>,
+    pub threads: Option<usize>,
+    /// Size per second such as "10MB", as for `--max-rate`
+    pub max_rate: Option<String>,
+    pub log_level: Option<String>,
+    pub log_format: Option<String>,
+}
+
+/// Reads `explicit`, or the first config file found; `None` if there is none
+pub fn load_config(explicit: Option<&Path>) -> anyhow::Result<Option<(PathBuf, ConfigFile)>> {
+    let path = match explicit {
+        Some(path) => path.to_path_buf(),
+        None => match find_config() {
+            Some(path) => path,
+            None => return Ok(None),
+        },
+    };
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow::anyhow!("Cannot read config file {:?}: {}", path, e))?;
+    let config = toml::from_str(&text)
+        .map_err(|e| anyhow::anyhow!("Invalid config file {:?}: {}", path, e))?;
+    Ok(Some((path, config)))
+}
+
+fn find_config() -> Option<PathBuf> {
+    let local = PathBuf::from(CONFIG_FILE_NAME);
+    if local.is_file() {
+        return Some(local);
+    }
+    let global = profiles_dir().ok()?.join(CONFIG_FILE_NAME);
+    global.is_file().then_some(global)
+}
@@ -0,0 +1,44 @@
+// src/desktop_notify.rs
+//! Desktop notification at the end of a run (`organize --notify`, the GUI
+//! checkbox). Not being able to show one never fails the run.
+
+use log::warn;
+use notify_rust::Notification;
+use smart_file_organizer::{organizer::OrganizeReport, units::format_size};
+
+const APP_NAME: &str = "Smart File Organizer";
+
+/// Files moved (or planned, in a dry run) and failed
+pub fn notify_finished(report: &OrganizeReport, dry_run: bool) {
+    let (summary, verb) = if dry_run {
+        ("Dry run complete", "to move")
+    } else {
+        ("Organizing complete", "moved")
+    };
+    let mut body = format!(
+        "{} file(s) {}, {}",
+        report.files_moved,
+        verb,
+        format_size(report.bytes_moved)
+    );
+    if report.failed > 0 {
+        body.push_str(&format!("; {} failed", report.failed));
+    }
+    show(summary, &body);
+}
+
+/// The run stopped with `error` before finishing
+pub fn notify_failed(error: &str) {
+    show("Organizing failed", error);
+}
+
+fn show(summary: &str, body: &str) {
+    if let Err(e) = Notification::new()
+        .appname(APP_NAME)
+        .summary(summary)
+        .body(body)
+        .show()
+    {
+        warn!("Cannot show a desktop notification: {}", e);
+    }
+}
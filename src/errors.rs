@@ -1,5 +1,6 @@
+use crate::units::format_size;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
-use std::path::PathBuf;
 
 #[derive(Error, Debug)]
 pub enum OrganizerError {
@@ -9,12 +10,126 @@ pub enum OrganizerError {
     #[error("Serde error: {0}")]
     Serde(#[from] serde_json::Error),
 
-    #[error("Permission denied for path: {0}")]
-    PermissionDenied(PathBuf),
+    #[error("Permission denied for path: {path}")]
+    PermissionDenied {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Cannot use source folder {path:?}: {source}")]
+    SourceUnavailable {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Cannot create target folder {path:?}: {source}")]
+    TargetDir {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+
+    #[error("Another run in progress ({owner}); lock file {lock:?}")]
+    RunInProgress { owner: String, lock: PathBuf },
+
+    #[error("File is in use by another process: {0}")]
+    FileInUse(PathBuf),
 
     #[error("File already exists at destination: {0}")]
     DestinationExists(PathBuf),
 
+    #[error("Copy {to:?} does not match the original {from:?}; both files were kept")]
+    CopyMismatch { from: PathBuf, to: PathBuf },
+
+    #[error(
+        "Not enough space for {path:?}: needs {}, {} free at the destination",
+        format_size(*needed),
+        format_size(*available)
+    )]
+    InsufficientSpace {
+        path: PathBuf,
+        needed: u64,
+        available: u64,
+    },
+
+    #[error("Copy of {0:?} was cancelled; the original was kept")]
+    CopyCancelled(PathBuf),
+
+    #[error(
+        "Could not undo {} moves; they are kept in the history: {}",
+        .0.len(),
+        format_paths(.0)
+    )]
+    UndoIncomplete(Vec<PathBuf>),
+
+    #[error("Name {name:?} is not valid on this system: it {reason}")]
+    InvalidName { name: String, reason: String },
+
+    #[error("{0:?} is a file, but the rules need a folder of that name there")]
+    FileInTheWay(PathBuf),
+
+    #[error("Target folder {0:?} from the rules points outside the destination")]
+    UnsafeTarget(String),
+
+    #[error("Cannot hardlink {from:?} as {to:?}: they are on different filesystems")]
+    CrossDeviceLink { from: PathBuf, to: PathBuf },
+
+    #[error("Cannot move {path:?} to the trash: {reason}")]
+    Trash { path: PathBuf, reason: String },
+
     #[error("Other error: {0}")]
     Other(String),
 }
+
+impl OrganizerError {
+    /// Wraps an I/O error that happened on `path`; permission problems become
+    /// `PermissionDenied` and files held open by another process `FileInUse`,
+    /// so callers can tell them apart from other failures
+    pub fn io_at(path: &Path, e: std::io::Error) -> Self {
+        if is_in_use(&e) {
+            OrganizerError::FileInUse(path.to_path_buf())
+        } else if e.kind() == std::io::ErrorKind::PermissionDenied {
+            OrganizerError::PermissionDenied {
+                path: path.to_path_buf(),
+                source: e,
+            }
+        } else {
+            OrganizerError::Io(e)
+        }
+    }
+
+    /// Errors about the destination that will hit the remaining files as
+    /// well (a target folder that can't be made, a full disk), as opposed to
+    /// a problem with one file or category folder (`FileInTheWay`)
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            OrganizerError::TargetDir { .. } => true,
+            OrganizerError::Io(e) => matches!(
+                e.kind(),
+                std::io::ErrorKind::StorageFull | std::io::ErrorKind::QuotaExceeded
+            ),
+            _ => false,
+        }
+    }
+}
+
+fn format_paths(paths: &[PathBuf]) -> String {
+    paths
+        .iter()
+        .map(|p| format!("{:?}", p))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Sharing/lock violation on Windows; elsewhere only a running executable
+/// refuses to be opened for writing
+fn is_in_use(e: &std::io::Error) -> bool {
+    if cfg!(windows) {
+        // ERROR_SHARING_VIOLATION, ERROR_LOCK_VIOLATION
+        matches!(e.raw_os_error(), Some(32 | 33))
+    } else {
+        e.kind() == std::io::ErrorKind::ExecutableFileBusy
+    }
+}
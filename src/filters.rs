@@ -0,0 +1,102 @@
+use crate::errors::OrganizerError;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+
+/// Ignore file read from the root of the source folder, gitignore syntax
+pub const IGNORE_FILE_NAME: &str = ".organizerignore";
+
+/// Include/exclude glob patterns, matched against paths relative to the source folder
+pub struct PathFilter {
+    /// `None` means "include everything"
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+    /// Patterns of the ignore files in the source root, if there are any
+    ignore: Option<Gitignore>,
+    /// The ignore files that were read; they stay put themselves
+    ignore_files: Vec<&'static str>,
+}
+
+impl PathFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, OrganizerError> {
+        Ok(Self {
+            include: if include.is_empty() {
+                None
+            } else {
+                Some(build_set(include)?)
+            },
+            exclude: build_set(exclude)?,
+            ignore: None,
+            ignore_files: Vec::new(),
+        })
+    }
+
+    /// Also excludes what `.organizerignore` in `root` lists and, with
+    /// `gitignore`, what its `.gitignore` lists plus the `.git` folder
+    pub fn with_ignore_files(
+        mut self,
+        root: &Path,
+        gitignore: bool,
+    ) -> Result<Self, OrganizerError> {
+        let mut builder = GitignoreBuilder::new(root);
+        let mut names = vec![IGNORE_FILE_NAME];
+        if gitignore {
+            names.push(".gitignore");
+            builder
+                .add_line(None, ".git/")
+                .map_err(|e| OrganizerError::Other(e.to_string()))?;
+        }
+        for name in names {
+            let path = root.join(name);
+            if !path.is_file() {
+                continue;
+            }
+            if let Some(e) = builder.add(&path) {
+                return Err(OrganizerError::Other(format!(
+                    "Invalid ignore file {:?}: {}",
+                    path, e
+                )));
+            }
+            self.ignore_files.push(name);
+        }
+        let ignore = builder
+            .build()
+            .map_err(|e| OrganizerError::Other(format!("Invalid ignore patterns: {}", e)))?;
+        self.ignore = (!ignore.is_empty()).then_some(ignore);
+        Ok(self)
+    }
+
+    /// Directories matching an exclude or ignore pattern are not descended into
+    pub fn excludes_dir(&self, rel: &Path) -> bool {
+        self.exclude.is_match(rel) || self.is_ignored(rel, true)
+    }
+
+    /// Exclude and ignore patterns win over include
+    pub fn allows_file(&self, rel: &Path) -> bool {
+        if self.exclude.is_match(rel) || self.is_ignored(rel, false) {
+            return false;
+        }
+        if self.ignore_files.iter().any(|name| rel == Path::new(name)) {
+            return false;
+        }
+        self.include.as_ref().is_none_or(|set| set.is_match(rel))
+    }
+
+    fn is_ignored(&self, rel: &Path, is_dir: bool) -> bool {
+        self.ignore
+            .as_ref()
+            .is_some_and(|ignore| ignore.matched(rel, is_dir).is_ignore())
+    }
+}
+
+fn build_set(patterns: &[String]) -> Result<GlobSet, OrganizerError> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| OrganizerError::Other(format!("Invalid glob {:?}: {}", pattern, e)))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map_err(|e| OrganizerError::Other(format!("Invalid glob set: {}", e)))
+}
@@ -0,0 +1,144 @@
+//! SHA-256 hashes of files kept between runs, so comparing large files
+//! (`skip-if-identical`, `hash-suffix`) doesn't read them again while they
+//! are unchanged
+use crate::errors::OrganizerError;
+use log::{debug, warn};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::SystemTime,
+};
+
+/// A hash stays valid while the file keeps this size and modification time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    modified: SystemTime,
+    sha256: String,
+}
+
+/// Hashes by absolute path, stored as JSON at `path`
+pub struct HashCache {
+    path: PathBuf,
+    entries: Mutex<HashMap<PathBuf, CacheEntry>>,
+    dirty: AtomicBool,
+}
+
+impl HashCache {
+    /// Reads the cache at `path`; a missing or unreadable file gives an empty cache
+    pub fn load(path: PathBuf) -> Self {
+        let entries = match fs::read_to_string(&path) {
+            Ok(data) => serde_json::from_str(&data).unwrap_or_else(|e| {
+                warn!("Ignoring invalid hash cache {:?}: {}", path, e);
+                HashMap::new()
+            }),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => {
+                warn!("Cannot read hash cache {:?}: {}", path, e);
+                HashMap::new()
+            }
+        };
+        Self {
+            path,
+            entries: Mutex::new(entries),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Cached hash of `file`, unless its size or modification time changed since
+    pub fn get(&self, file: &Path) -> Option<String> {
+        let key = key(file)?;
+        let meta = fs::metadata(file).ok()?;
+        let mut entries = self.entries.lock();
+        let entry = entries.get(&key)?;
+        if entry.size == meta.len() && meta.modified().ok() == Some(entry.modified) {
+            return Some(entry.sha256.clone());
+        }
+        entries.remove(&key);
+        self.dirty.store(true, Ordering::Relaxed);
+        None
+    }
+
+    /// Remembers `sha256` for the current size and modification time of `file`
+    pub fn insert(&self, file: &Path, sha256: String) {
+        let (Some(key), Ok(meta)) = (key(file), fs::metadata(file)) else {
+            return;
+        };
+        let Ok(modified) = meta.modified() else {
+            return;
+        };
+        let entry = CacheEntry {
+            size: meta.len(),
+            modified,
+            sha256,
+        };
+        self.entries.lock().insert(key, entry);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Hex SHA-256 of `file`, from the cache or read and then cached
+    pub fn hash(&self, file: &Path) -> io::Result<String> {
+        if let Some(hash) = self.get(file) {
+            return Ok(hash);
+        }
+        let hash = file_hash(file)?;
+        self.insert(file, hash.clone());
+        Ok(hash)
+    }
+
+    /// Carries the entry of a renamed file over to its new path; if the
+    /// rename changed the modification time it is dropped on the next `get`
+    pub fn rename(&self, from: &Path, to: &Path) {
+        let (Some(from), Some(to)) = (key(from), key(to)) else {
+            return;
+        };
+        let mut entries = self.entries.lock();
+        if let Some(entry) = entries.remove(&from) {
+            entries.insert(to, entry);
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Writes the cache if it changed, leaving out files that no longer exist
+    pub fn save(&self) -> Result<(), OrganizerError> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut entries = self.entries.lock();
+        entries.retain(|path, _| path.is_file());
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| OrganizerError::io_at(parent, e))?;
+        }
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        fs::write(&tmp, serde_json::to_string(&*entries)?)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|e| OrganizerError::io_at(&self.path, e))?;
+        debug!("Saved {} hash(es) to {:?}", entries.len(), self.path);
+        Ok(())
+    }
+}
+
+/// Hex SHA-256 of the file contents
+pub(crate) fn file_hash(path: &Path) -> io::Result<String> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Absolute path; non-UTF-8 paths are skipped since JSON object keys must be strings
+fn key(file: &Path) -> Option<PathBuf> {
+    std::path::absolute(file)
+        .ok()
+        .filter(|p| p.to_str().is_some())
+}
@@ -1,57 +1,574 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
-use std::{fs, path::PathBuf};
 use crate::errors::OrganizerError;
+use chrono::{DateTime, Local, Utc};
+use log::{error, info, warn};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+/// A batch is written to disk at least every this many entries,
+/// so a crash mid-run loses at most this many records.
+const BATCH_FLUSH_EVERY: usize = 256;
+
+/// How a file reached its destination
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransferKind {
+    #[default]
+    Move,
+    /// The original was left in place; undo deletes the copy
+    Copy,
+    /// A hard link to the original was created; undo deletes the link
+    HardLink,
+    /// A symbolic link to the original was created; undo deletes the link
+    Symlink,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MovedFile {
     pub from: PathBuf,
     pub to: PathBuf,
     pub time: DateTime<Utc>,
+    /// `organize()` run that made this move; 0 for entries recorded before sessions existed
+    #[serde(default)]
+    pub session_id: u64,
+    #[serde(default)]
+    pub kind: TransferKind,
+    /// The file this one replaced at `to` was sent to the trash (see
+    /// `OrganizerConfig::use_trash`); undo can't bring it back
+    #[serde(default)]
+    pub replaced_trashed: bool,
+    /// Rule pattern or engine that chose the target folder ("fallback" if
+    /// none matched); `None` for moves recorded before this was kept
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule_matched: Option<String>,
 }
 
-#[derive(Debug, Default, Serialize, Deserialize)]
+/// Source folder deleted by the empty-folder cleanup after a run
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RemovedDir {
+    pub path: PathBuf,
+    pub time: DateTime<Utc>,
+    pub session_id: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct History {
     pub moves: Vec<MovedFile>,
+    #[serde(default)]
+    pub removed_dirs: Vec<RemovedDir>,
+    /// Highest session id handed out so far
+    #[serde(default)]
+    pub last_session: u64,
+    /// Moves reverted by the undo commands, the next one to redo last.
+    /// Cleared by the next `organize()` run that moves anything.
+    #[serde(default)]
+    pub redo: Vec<MovedFile>,
+}
+
+/// Summary of one `organize()` run as returned by `History::list_sessions`
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionInfo {
+    pub id: u64,
+    pub started: DateTime<Utc>,
+    pub files: usize,
+}
+
+impl History {
+    pub fn list_sessions(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> = Vec::new();
+        for mov in &self.moves {
+            match sessions.iter_mut().find(|s| s.id == mov.session_id) {
+                Some(session) => {
+                    session.files += 1;
+                    session.started = session.started.min(mov.time);
+                }
+                None => sessions.push(SessionInfo {
+                    id: mov.session_id,
+                    started: mov.time,
+                    files: 1,
+                }),
+            }
+        }
+        sessions
+    }
+
+    fn next_session_id(&self) -> u64 {
+        let max_recorded = self.moves.iter().map(|m| m.session_id).max().unwrap_or(0);
+        self.last_session.max(max_recorded) + 1
+    }
 }
 
 pub struct HistoryManager {
     path: PathBuf,
+    /// Fail on a corrupt history file instead of setting it aside
+    strict: bool,
+    /// Most moves kept; older ones are pruned when new ones are recorded
+    limit: Option<usize>,
+    /// Moves older than this are pruned when new ones are recorded
+    max_age: Option<Duration>,
+    /// The history as last read or written, shared by all workers
+    state: Mutex<State>,
+}
+
+/// In-memory copy of the history file. It is read again only when the file
+/// changed on disk since (another process ran an undo, say).
+#[derive(Default)]
+struct State {
+    /// `None` until first needed
+    history: Option<History>,
+    /// Size and modification time of the file when `history` was read or
+    /// written; `None` if there was no file
+    stamp: Option<(u64, SystemTime)>,
+    /// Moves from `push` not written yet; see `flush`
+    pending: Vec<MovedFile>,
 }
 
 impl HistoryManager {
-    pub fn new(path: PathBuf) -> Self { Self { path } }
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            strict: false,
+            limit: None,
+            max_age: None,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    pub fn with_limit(mut self, limit: Option<usize>) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    pub fn with_max_age(mut self, max_age: Option<Duration>) -> Self {
+        self.max_age = max_age;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
 
+    /// The recorded history, including moves not flushed yet.
+    /// A file that is not valid history JSON is renamed to
+    /// `<name>.corrupt-<timestamp>` and an empty history is returned,
+    /// so one bad file doesn't block every later run (unless strict).
     pub fn load(&self) -> Result<History, OrganizerError> {
+        let mut state = self.state.lock();
+        let mut history = self.current(&mut state)?.clone();
+        history.moves.extend(state.pending.iter().cloned());
+        Ok(history)
+    }
+
+    /// Size and modification time of the history file; `None` if it is missing
+    fn disk_stamp(&self) -> Result<Option<(u64, SystemTime)>, OrganizerError> {
+        match fs::metadata(&self.path) {
+            Ok(meta) => Ok(Some((meta.len(), meta.modified()?))),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The cached history, read again if the file changed on disk
+    fn current<'s>(&self, state: &'s mut State) -> Result<&'s mut History, OrganizerError> {
+        let stamp = self.disk_stamp()?;
+        if state.history.is_none() || state.stamp != stamp {
+            let history = self.read_file()?;
+            // a corrupt file was moved aside
+            state.stamp = self.disk_stamp()?;
+            state.history = Some(history);
+        }
+        Ok(state.history.get_or_insert_with(History::default))
+    }
+
+    /// Runs `f` on the history with the pending moves added, and writes
+    /// the result at once; `f` returns whether it changed anything
+    fn update<T>(&self, f: impl FnOnce(&mut History) -> (T, bool)) -> Result<T, OrganizerError> {
+        let mut state = self.state.lock();
+        let unsaved = std::mem::take(&mut state.pending);
+        let flushing = !unsaved.is_empty();
+        let history = match self.current(&mut state) {
+            Ok(history) => history,
+            Err(e) => {
+                state.pending = unsaved;
+                return Err(e);
+            }
+        };
+        for mov in &unsaved {
+            history.last_session = history.last_session.max(mov.session_id);
+        }
+        history.moves.extend(unsaved.iter().cloned());
+        if flushing {
+            self.prune(history);
+        }
+        let (res, changed) = f(history);
+        if changed || flushing {
+            if let Err(e) = self.save(history) {
+                // forget the change; the moves are written with the next one
+                state.history = None;
+                state.pending = unsaved;
+                return Err(e);
+            }
+            state.stamp = self.disk_stamp()?;
+        }
+        Ok(res)
+    }
+
+    fn read_file(&self) -> Result<History, OrganizerError> {
         if !self.path.exists() {
             return Ok(History::default());
         }
         let data = fs::read_to_string(&self.path)?;
-        Ok(serde_json::from_str(&data)?)
+        match serde_json::from_str(&data) {
+            Ok(history) => Ok(history),
+            Err(e) if self.strict => Err(e.into()),
+            Err(e) => {
+                let mut backup = self.path.clone().into_os_string();
+                backup.push(format!(".corrupt-{}", Local::now().format("%Y%m%d-%H%M%S")));
+                let backup = PathBuf::from(backup);
+                fs::rename(&self.path, &backup)?;
+                warn!(
+                    "History file {:?} is corrupt ({}); moved it to {:?} and starting a new history",
+                    self.path, e, backup
+                );
+                Ok(History::default())
+            }
+        }
     }
 
+    /// Writes to a temporary file and renames it over the old one,
+    /// so an interrupted write never leaves truncated JSON behind.
     pub fn save(&self, history: &History) -> Result<(), OrganizerError> {
-        fs::write(&self.path, serde_json::to_string_pretty(history)?)?;
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        let tmp = PathBuf::from(tmp);
+        if let Some(parent) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&tmp, serde_json::to_string_pretty(history)?)?;
+        fs::rename(&tmp, &self.path)?;
         Ok(())
     }
 
+    /// Records a move in memory; it is written by `flush`, by any other
+    /// change to the history, or when the manager is dropped
     pub fn push(&self, moved: MovedFile) -> Result<(), OrganizerError> {
-        let mut history = self.load()?;
-        history.moves.push(moved);
-        self.save(&history)
+        self.state.lock().pending.push(moved);
+        Ok(())
+    }
+
+    /// Writes the moves recorded with `push` since the last write
+    pub fn flush(&self) -> Result<(), OrganizerError> {
+        if self.state.lock().pending.is_empty() {
+            return Ok(());
+        }
+        self.update(|_| ((), false))
+    }
+
+    fn append(&self, moves: Vec<MovedFile>) -> Result<(), OrganizerError> {
+        if moves.is_empty() {
+            return Ok(());
+        }
+        self.state.lock().pending.extend(moves);
+        self.flush()
+    }
+
+    /// Applies `limit` and `max_age`, oldest moves first. The newest session
+    /// is never pruned: it may be the run that is still recording.
+    fn prune(&self, history: &mut History) {
+        let current = history.last_session;
+        let before = history.moves.len();
+        let cutoff = self
+            .max_age
+            .and_then(|age| chrono::Duration::from_std(age).ok())
+            .and_then(|age| Utc::now().checked_sub_signed(age));
+        if let Some(cutoff) = cutoff {
+            history
+                .moves
+                .retain(|m| m.session_id == current || m.time >= cutoff);
+            history
+                .removed_dirs
+                .retain(|d| d.session_id == current || d.time >= cutoff);
+        }
+        if let Some(limit) = self.limit {
+            let mut excess = history.moves.len().saturating_sub(limit);
+            history.moves.retain(|m| {
+                if excess > 0 && m.session_id != current {
+                    excess -= 1;
+                    return false;
+                }
+                true
+            });
+        }
+        let pruned = before - history.moves.len();
+        if pruned > 0 {
+            info!(
+                "Pruned {} old move(s) from {:?}; they can no longer be undone",
+                pruned, self.path
+            );
+        }
+    }
+
+    pub fn record_removed_dirs(&self, dirs: Vec<RemovedDir>) -> Result<(), OrganizerError> {
+        if dirs.is_empty() {
+            return Ok(());
+        }
+        self.update(|history| {
+            history.removed_dirs.extend(dirs);
+            ((), true)
+        })
+    }
+
+    /// Removes and returns the recorded removed folders accepted by `pred`
+    pub fn take_removed_dirs(
+        &self,
+        pred: impl Fn(&RemovedDir) -> bool,
+    ) -> Result<Vec<RemovedDir>, OrganizerError> {
+        self.update(|history| {
+            let (res, keep): (Vec<_>, _) = std::mem::take(&mut history.removed_dirs)
+                .into_iter()
+                .partition(|d| pred(d));
+            history.removed_dirs = keep;
+            let changed = !res.is_empty();
+            (res, changed)
+        })
+    }
+
+    /// Starts buffering moves in memory; they are written on `commit()`,
+    /// on drop, or every `BATCH_FLUSH_EVERY` entries.
+    /// The batch also allocates a new session id for the run.
+    pub fn begin_batch(&self) -> Result<HistoryBatch<'_>, OrganizerError> {
+        let session_id = self.load()?.next_session_id();
+        Ok(HistoryBatch {
+            manager: self,
+            session_id,
+            pending: Mutex::new(Vec::new()),
+        })
     }
 
     pub fn pop_last(&self) -> Result<Option<MovedFile>, OrganizerError> {
-        let mut history = self.load()?;
-        let res = history.moves.pop();
-        self.save(&history)?;
-        Ok(res)
+        self.update(|history| {
+            let res = history.moves.pop();
+            let changed = res.is_some();
+            (res, changed)
+        })
     }
 
     pub fn take_all(&self) -> Result<Vec<MovedFile>, OrganizerError> {
-        let mut history = self.load()?;
-        let res = std::mem::take(&mut history.moves);
-        self.save(&history)?;
-        Ok(res)
+        self.update(|history| {
+            let res = std::mem::take(&mut history.moves);
+            let changed = !res.is_empty();
+            (res, changed)
+        })
+    }
+
+    /// Adds reverted moves to the redo stack, in the order they were reverted
+    pub fn push_redo(&self, undone: Vec<MovedFile>) -> Result<(), OrganizerError> {
+        if undone.is_empty() {
+            return Ok(());
+        }
+        self.update(|history| {
+            history.redo.extend(undone);
+            ((), true)
+        })
+    }
+
+    /// Puts moves taken out by an undo back into the history, in time order
+    pub fn restore(&self, moves: Vec<MovedFile>) -> Result<(), OrganizerError> {
+        if moves.is_empty() {
+            return Ok(());
+        }
+        self.update(|history| {
+            history.moves.extend(moves);
+            history.moves.sort_by_key(|m| m.time);
+            ((), true)
+        })
+    }
+
+    pub fn pop_redo(&self) -> Result<Option<MovedFile>, OrganizerError> {
+        self.update(|history| {
+            let res = history.redo.pop();
+            let changed = res.is_some();
+            (res, changed)
+        })
+    }
+
+    pub fn clear_redo(&self) -> Result<(), OrganizerError> {
+        self.update(|history| {
+            let changed = !history.redo.is_empty();
+            history.redo.clear();
+            ((), changed)
+        })
+    }
+
+    /// Removes and returns all moves recorded by the given session
+    pub fn take_session(&self, session_id: u64) -> Result<Vec<MovedFile>, OrganizerError> {
+        self.take_matching(|m| m.session_id == session_id)
+    }
+
+    /// Removes and returns the most recent move accepted by `pred`; the
+    /// other moves keep their order
+    pub fn take_latest_matching(
+        &self,
+        pred: impl Fn(&MovedFile) -> bool,
+    ) -> Result<Option<MovedFile>, OrganizerError> {
+        self.update(|history| {
+            let latest = history
+                .moves
+                .iter()
+                .enumerate()
+                .filter(|(_, m)| pred(m))
+                .max_by_key(|(i, m)| (m.time, *i))
+                .map(|(i, _)| i);
+            match latest {
+                Some(index) => (Some(history.moves.remove(index)), true),
+                None => (None, false),
+            }
+        })
+    }
+
+    /// Removes and returns the moves accepted by `pred`, keeping their order
+    pub fn take_matching(
+        &self,
+        pred: impl Fn(&MovedFile) -> bool,
+    ) -> Result<Vec<MovedFile>, OrganizerError> {
+        self.update(|history| {
+            let (res, keep): (Vec<_>, _) = std::mem::take(&mut history.moves)
+                .into_iter()
+                .partition(|m| pred(m));
+            history.moves = keep;
+            let changed = !res.is_empty();
+            (res, changed)
+        })
+    }
+}
+
+impl Drop for HistoryManager {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("Failed to write history: {}", e);
+        }
+    }
+}
+
+/// Buffered writer returned by `HistoryManager::begin_batch`
+pub struct HistoryBatch<'a> {
+    manager: &'a HistoryManager,
+    session_id: u64,
+    pending: Mutex<Vec<MovedFile>>,
+}
+
+impl HistoryBatch<'_> {
+    pub fn session_id(&self) -> u64 {
+        self.session_id
+    }
+
+    pub fn push(&self, moved: MovedFile) -> Result<(), OrganizerError> {
+        let full = {
+            let mut pending = self.pending.lock();
+            pending.push(moved);
+            pending.len() >= BATCH_FLUSH_EVERY
+        };
+        if full {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&self) -> Result<(), OrganizerError> {
+        let moves = std::mem::take(&mut *self.pending.lock());
+        self.manager.append(moves)
+    }
+
+    pub fn commit(self) -> Result<(), OrganizerError> {
+        self.flush()
+    }
+}
+
+impl Drop for HistoryBatch<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush() {
+            error!("Failed to write history: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moved(thread: usize, n: usize) -> MovedFile {
+        MovedFile {
+            from: PathBuf::from(format!("/src/{}-{}.txt", thread, n)),
+            to: PathBuf::from(format!("/dst/txt/{}-{}.txt", thread, n)),
+            time: Utc::now(),
+            session_id: 1,
+            kind: TransferKind::Move,
+            replaced_trashed: false,
+            rule_matched: None,
+        }
+    }
+
+    #[test]
+    fn concurrent_pushes_are_all_written() {
+        const THREADS: usize = 8;
+        const PER_THREAD: usize = 100;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+        let manager = HistoryManager::new(path.clone());
+        std::thread::scope(|scope| {
+            for thread in 0..THREADS {
+                let manager = &manager;
+                scope.spawn(move || {
+                    for n in 0..PER_THREAD {
+                        manager.push(moved(thread, n)).unwrap();
+                        if n % 10 == 0 {
+                            manager.flush().unwrap();
+                        }
+                    }
+                });
+            }
+        });
+        manager.flush().unwrap();
+
+        let on_disk: History = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(on_disk.moves.len(), THREADS * PER_THREAD);
+        let mut names: Vec<_> = on_disk.moves.iter().map(|m| m.from.clone()).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), THREADS * PER_THREAD);
+        assert_eq!(manager.load().unwrap().moves.len(), THREADS * PER_THREAD);
+    }
+
+    #[test]
+    fn concurrent_batch_pushes_are_all_written() {
+        const THREADS: usize = 8;
+        // enough for several batch flushes in between
+        const PER_THREAD: usize = BATCH_FLUSH_EVERY;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("history.json");
+        let manager = HistoryManager::new(path.clone());
+        let batch = manager.begin_batch().unwrap();
+        std::thread::scope(|scope| {
+            for thread in 0..THREADS {
+                let batch = &batch;
+                scope.spawn(move || {
+                    for n in 0..PER_THREAD {
+                        batch.push(moved(thread, n)).unwrap();
+                    }
+                });
+            }
+        });
+        batch.commit().unwrap();
+
+        let on_disk: History = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(on_disk.moves.len(), THREADS * PER_THREAD);
     }
 }
@@ -0,0 +1,66 @@
+//! Smart File Organizer as a library: sorts the files of a folder into
+//! category subfolders and records every move so it can be undone.
+//!
+//! The stable public API is what is re-exported at the crate root:
+//! [`Organizer`], [`OrganizerConfig`], [`RuleEngine`] with [`FileMeta`] and the
+//! bundled rule engines, [`HistoryManager`], [`MovedFile`] and [`OrganizerError`].
+//! Everything else reachable through the modules (logging setup, watch mode,
+//! rule profiles, size parsing, the hash cache) exists for the bundled CLI/GUI and may change between
+//! minor versions.
+//!
+//! ```no_run
+//! use smart_file_organizer::{ExtensionRuleEngine, HistoryManager, Organizer, OrganizerConfig};
+//!
+//! let organizer = Organizer::new(
+//!     OrganizerConfig {
+//!         src_dir: "Downloads".into(),
+//!         dst_dir: "Downloads".into(),
+//!         ..Default::default()
+//!     },
+//!     ExtensionRuleEngine::default(),
+//!     HistoryManager::new("history.json".into()),
+//! );
+//! let report = organizer.organize()?;
+//! println!("{} file(s) moved", report.files_moved);
+//! # Ok::<(), smart_file_organizer::OrganizerError>(())
+//! ```
+//!
+//! Since 0.2 [`RuleEngine::classify`] receives the file's [`FileMeta`] (size,
+//! modification time, MIME type sniffed on first use) and returns a `Result`,
+//! so an engine can fail for one file without stopping the run:
+//!
+//! ```
+//! use smart_file_organizer::{FileMeta, OrganizerError, RuleEngine};
+//! use std::path::Path;
+//!
+//! struct BySize;
+//!
+//! impl RuleEngine for BySize {
+//!     fn classify(&self, _file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+//!         Ok(if meta.size > 100 * 1024 * 1024 { "Large" } else { "Small" }.to_string())
+//!     }
+//! }
+//! ```
+
+pub mod errors;
+mod filters;
+pub mod hash_cache;
+pub mod history;
+pub mod logger;
+pub mod organizer;
+mod path_sanitize;
+pub mod profiles;
+pub mod rules;
+mod run_lock;
+pub mod units;
+pub mod watch;
+
+pub use errors::OrganizerError;
+pub use history::{HistoryManager, MovedFile};
+pub use organizer::{Organizer, OrganizerConfig};
+pub use rules::{
+    AgeRuleEngine, ChainRuleEngine, Classification, CompositeRuleEngine, CustomRule,
+    CustomRuleEngine, DateRuleEngine, ExifDateRuleEngine, ExtensionRuleEngine, FileMeta,
+    FolderCase, GroupRuleEngine, MimeRuleEngine, PrefixRuleEngine, RuleEngine, RulesConfig,
+    TemplateRuleEngine,
+};
@@ -1,26 +1,230 @@
 use chrono::Local;
-use fern::Dispatch;
-use log::LevelFilter;
-use std::{fs, path::PathBuf};
+use clap::ValueEnum;
+use fern::{Dispatch, FormatCallback};
+use log::{LevelFilter, Record};
+use std::{
+    fmt::Arguments,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
 
-pub fn setup_logging(log_path: PathBuf) -> anyhow::Result<()> {
+/// Default size at which the log file is rotated
+pub const DEFAULT_MAX_LOG_BYTES: u64 = 10 << 20;
+/// Default number of rotated files (`organizer.log.1` …) to keep
+pub const DEFAULT_LOG_KEEP: usize = 5;
+
+/// Line format of a log sink
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// `2024-01-01 10:00:00 [INFO] message`
+    #[default]
+    Text,
+    /// One JSON object per line: `{"ts", "level", "target", "msg"}`
+    Json,
+}
+
+impl LogFormat {
+    fn dispatch(self) -> Dispatch {
+        match self {
+            LogFormat::Text => Dispatch::new().format(format_text),
+            LogFormat::Json => Dispatch::new().format(format_json),
+        }
+    }
+}
+
+fn format_text(out: FormatCallback, message: &Arguments, record: &Record) {
+    out.finish(format_args!(
+        "{} [{}] {}",
+        Local::now().format("%Y-%m-%d %H:%M:%S"),
+        record.level(),
+        message
+    ))
+}
+
+fn format_json(out: FormatCallback, message: &Arguments, record: &Record) {
+    let line = serde_json::json!({
+        "ts": Local::now().to_rfc3339(),
+        "level": record.level().as_str(),
+        "target": record.target(),
+        "msg": message.to_string(),
+    });
+    out.finish(format_args!("{}", line))
+}
+
+/// Everything `setup_logging` needs besides the file path
+#[derive(Debug, Clone, Copy)]
+pub struct LogOptions {
+    pub level: LevelFilter,
+    /// Also log to stdout; the log file always gets every line
+    pub stdout: bool,
+    /// Write the log file; off for dry runs, which leave the disk as it is
+    pub file: bool,
+    pub stdout_format: LogFormat,
+    pub file_format: LogFormat,
+    /// Rotate the log file once it grows past this size
+    pub max_log_bytes: u64,
+    /// Number of rotated files to keep; older ones are deleted
+    pub log_keep: usize,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            level: LevelFilter::Info,
+            stdout: true,
+            file: true,
+            stdout_format: LogFormat::Text,
+            file_format: LogFormat::Text,
+            max_log_bytes: DEFAULT_MAX_LOG_BYTES,
+            log_keep: DEFAULT_LOG_KEEP,
+        }
+    }
+}
+
+/// Parses `error|warn|info|debug|trace|off` (case-insensitive)
+pub fn parse_level(s: &str) -> Result<LevelFilter, String> {
+    s.parse::<LevelFilter>().map_err(|_| {
+        format!(
+            "unknown log level {:?}, expected one of: off, error, warn, info, debug, trace",
+            s
+        )
+    })
+}
+
+/// Moves `base` up by `verbose` steps and down by `quiet` steps,
+/// clamped to `Off..=Trace`
+pub fn adjust_level(base: LevelFilter, verbose: u8, quiet: u8) -> LevelFilter {
+    let levels = LevelFilter::iter().collect::<Vec<_>>();
+    let idx = levels.iter().position(|l| *l == base).unwrap_or(0) as i32 + i32::from(verbose)
+        - i32::from(quiet);
+    levels[idx.clamp(0, levels.len() as i32 - 1) as usize]
+}
+
+pub fn setup_logging(log_path: PathBuf, options: &LogOptions) -> anyhow::Result<()> {
+    dispatch(log_path, options)?.apply()?;
+    Ok(())
+}
+
+/// Like `setup_logging`, and also hands every line (`HH:MM:SS [LEVEL] message`)
+/// to `sink`, e.g. to show it in the GUI
+pub fn setup_logging_with_sink(
+    log_path: PathBuf,
+    options: &LogOptions,
+    sink: impl Fn(String) + Send + Sync + 'static,
+) -> anyhow::Result<()> {
+    dispatch(log_path, options)?
+        .chain(
+            Dispatch::new()
+                .format(|out, message, record| {
+                    out.finish(format_args!(
+                        "{} [{}] {}",
+                        Local::now().format("%H:%M:%S"),
+                        record.level(),
+                        message
+                    ))
+                })
+                .chain(fern::Output::call(move |record| {
+                    sink(record.args().to_string())
+                })),
+        )
+        .apply()?;
+    Ok(())
+}
+
+fn dispatch(log_path: PathBuf, options: &LogOptions) -> anyhow::Result<Dispatch> {
+    let mut dispatch = Dispatch::new().level(options.level);
+    if options.stdout {
+        dispatch = dispatch.chain(options.stdout_format.dispatch().chain(std::io::stdout()));
+    }
+    if !options.file {
+        return Ok(dispatch);
+    }
     if let Some(parent) = log_path.parent() {
         fs::create_dir_all(parent)?;
     }
+    Ok(dispatch.chain(
+        options
+            .file_format
+            .dispatch()
+            .chain(Box::new(RotatingFile::open(
+                log_path,
+                options.max_log_bytes,
+                options.log_keep,
+            )?) as Box<dyn Write + Send>),
+    ))
+}
+
+/// Append-only log file that renames itself to `<name>.1` (shifting older
+/// rotations up to `<name>.<keep>`) once it exceeds `max_bytes`.
+/// A failed rotation is reported on stderr and logging simply continues
+/// in the current file, so it never blocks the organize run.
+struct RotatingFile {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+    file: File,
+    written: u64,
+}
 
-    Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "{} [{}] {}",
-                Local::now().format("%Y-%m-%d %H:%M:%S"),
-                record.level(),
-                message
-            ))
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64, keep: usize) -> io::Result<Self> {
+        let file = open_append(&path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_bytes,
+            keep,
+            file,
+            written,
         })
-        .level(LevelFilter::Info)
-        .chain(std::io::stdout())
-        .chain(fern::log_file(log_path)?)
-        .apply()?;
+    }
 
-    Ok(())
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated(self.keep));
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(&from, self.rotated(n + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = open_append(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            if let Err(e) = self.rotate() {
+                eprintln!("Log rotation failed for {:?}: {}", self.path, e);
+                // don't retry on every line
+                self.written = 0;
+            }
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
 }
@@ -1,20 +1,58 @@
-mod errors;
-mod history;
-mod logger;
-mod organizer;
-mod rules;
+mod config_file;
+mod desktop_notify;
 mod ui_cli;
 mod ui_gui;
+mod workspace;
 
-use clap::Parser;
-use ui_cli::run_cli;
+use smart_file_organizer::OrganizerError;
+use std::process::ExitCode;
+use ui_cli::{parse_args, run_cli, Cancelled, FilesFailed};
 
-fn main() -> anyhow::Result<()> {
-    let args = ui_cli::CliArgs::parse();
+/// Exit codes for scripts; 2 is what clap exits with on invalid arguments
+const EXIT_ERROR: u8 = 1;
+const EXIT_FILES_FAILED: u8 = 3;
+const EXIT_SOURCE: u8 = 4;
+const EXIT_LOCKED: u8 = 5;
+/// 128 + SIGINT, as shells report a process stopped by Ctrl-C
+const EXIT_CANCELLED: u8 = 130;
+
+fn main() -> ExitCode {
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    let args = parse_args()?;
+    if !args.dry_run() {
+        workspace::migrate_legacy_dir();
+    }
     if args.gui {
-        ui_gui::run_gui().map_err(|e| anyhow::anyhow!(e.to_string()))?;
+        ui_gui::run_gui(args.log_options(), args.log_file())
+            .map_err(|e| anyhow::anyhow!(e.to_string()))?;
         Ok(())
     } else {
-        run_cli()
+        run_cli(args)
+    }
+}
+
+fn exit_code(e: &anyhow::Error) -> u8 {
+    if e.is::<Cancelled>() {
+        return EXIT_CANCELLED;
+    }
+    if e.is::<FilesFailed>() {
+        return EXIT_FILES_FAILED;
+    }
+    match e
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<OrganizerError>())
+    {
+        Some(OrganizerError::SourceUnavailable { .. }) => EXIT_SOURCE,
+        Some(OrganizerError::RunInProgress { .. }) => EXIT_LOCKED,
+        _ => EXIT_ERROR,
     }
 }
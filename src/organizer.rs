@@ -1,170 +1,2534 @@
 use crate::{
     errors::OrganizerError,
-    history::{HistoryManager, MovedFile},
-    rules::RuleEngine,
+    filters::PathFilter,
+    hash_cache::{file_hash, HashCache},
+    history::{HistoryBatch, HistoryManager, MovedFile, RemovedDir, TransferKind},
+    path_sanitize::{invalid_reason, sanitize, Platform},
+    rules::{Classification, FileMeta, RuleEngine},
+    run_lock::RunLock,
 };
-use chrono::Utc;
-use log::{error, info, warn};
-use parking_lot::Mutex;
+use chrono::{DateTime, Local, Utc};
+use filetime::FileTime;
+use log::{debug, error, info, warn};
+use parking_lot::{Mutex, MutexGuard};
+use rayon::prelude::*;
+use serde::Serialize;
 use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::{OsStr, OsString},
     fs, io,
-    path::{Path, PathBuf},
+    path::{Component, Path, PathBuf},
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicUsize, Ordering},
         Arc,
     },
+    time::{Duration, Instant, SystemTime},
 };
+use unicode_normalization::UnicodeNormalization;
 use walkdir::WalkDir;
 
-#[derive(Debug, Clone)]
+/// Pause between attempts on a file that is in use (see `retry_locked`)
+const RETRY_LOCKED_DELAY: Duration = Duration::from_secs(1);
+/// How often a paused run checks whether it was resumed or cancelled
+const PAUSE_POLL: Duration = Duration::from_millis(100);
+/// Copies are written in pieces of this size; cancel and progress are checked between them
+const COPY_CHUNK: usize = 1 << 20;
+
+/// `report.pdf` -> `report_(1).pdf`
+pub const DEFAULT_CONFLICT_TEMPLATE: &str = "{stem}_({n}).{ext}";
+
+/// Extensions of files browsers and download tools are still writing
+/// (Chrome, Firefox, Edge, Safari, Opera, WinSCP, uTorrent, aria2)
+pub const DEFAULT_PARTIAL_EXTENSIONS: [&str; 9] = [
+    "crdownload",
+    "part",
+    "partial",
+    "download",
+    "opdownload",
+    "filepart",
+    "tmp",
+    "!ut",
+    "aria2",
+];
+/// A file modified more recently than this may still be being written
+pub const DEFAULT_MIN_AGE: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Default)]
 pub struct OrganizerConfig {
     pub src_dir: PathBuf,
+    /// More source folders organized in the same run as `src_dir`; the
+    /// filters apply to each, and conflicts are resolved across all of them
+    pub extra_src_dirs: Vec<PathBuf>,
     pub dst_dir: PathBuf,
     pub dry_run: bool,
-    pub overwrite: bool,
+    /// What to do when the target file name is already taken
+    pub on_conflict: ConflictStrategy,
+    /// Name given to a renamed file, from `{stem}`, `{n}` and `{ext}`
+    /// (`"{stem}-{n}.{ext}"`); `None` uses `DEFAULT_CONFLICT_TEMPLATE`.
+    /// See `validate_conflict_template`.
+    pub conflict_template: Option<String>,
+    /// Wait for a concurrent run on the same history to finish instead of failing
+    pub wait_for_lock: bool,
+    /// How often to retry a file that another process has open before skipping it
+    pub retry_locked: u32,
+    /// What to do when a rule or source file yields a name the destination OS rejects
+    pub on_invalid_name: InvalidNameStrategy,
+    /// Whether folders directly in a source folder are moved as a whole
+    pub treat_dirs: TreatDirs,
+    /// Unicode form for the destination names (`é` as one code point or as
+    /// `e` + accent, as macOS writes it); `None` keeps names as they are
+    pub normalize_unicode: Option<UnicodeForm>,
+    /// Number of worker threads; `None` uses all available CPUs
+    pub threads: Option<usize>,
+    /// Bytes per second to move or copy at most, spread over all workers;
+    /// `None` is unlimited
+    pub max_rate: Option<u64>,
+    /// Stop after this many files were moved (or, in dry-run mode, planned);
+    /// skipped and failed files don't count. The rest stays in place.
+    pub max_files: Option<usize>,
+    /// Maximum walk depth below `src_dir` (1 = only its direct children);
+    /// `None` recurses without limit
+    pub max_depth: Option<usize>,
+    /// Also organize dotfiles and (on Windows) hidden/system files
+    pub include_hidden: bool,
+    /// Glob patterns (relative to the source folder); when non-empty only matching files are organized
+    pub include: Vec<String>,
+    /// Glob patterns (relative to the source folder) that are never organized; wins over `include`
+    pub exclude: Vec<String>,
+    /// Only organize files of at least this many bytes
+    pub min_size: Option<u64>,
+    /// Only organize files of at most this many bytes
+    pub max_size: Option<u64>,
+    /// Leave zero-byte files where they are
+    pub skip_empty: bool,
+    /// Only organize files modified at most this long ago
+    pub newer_than: Option<Duration>,
+    /// Only organize files modified more than this long ago
+    pub older_than: Option<Duration>,
+    /// Files with these extensions (compared case-insensitively) are
+    /// downloads in progress and stay put; `None` uses `DEFAULT_PARTIAL_EXTENSIONS`
+    pub partial_extensions: Option<Vec<String>>,
+    /// Leave files modified less than this long ago, as they may still be
+    /// written; `None` uses `DEFAULT_MIN_AGE`, zero turns the check off
+    pub min_age: Option<Duration>,
+    /// Also skip what the `.gitignore` of `src_dir` lists, and `.git`;
+    /// `.organizerignore` there is always honored
+    pub respect_gitignore: bool,
+    /// When non-empty, only files the rules put into one of these categories
+    /// (compared case-insensitively) are organized; the rest stay in place
+    pub only_categories: Vec<String>,
+    /// Keep the source subfolder layout under each category (`dst/pdf/a/report.pdf`)
+    pub preserve_tree: bool,
+    /// Put each file into a subfolder named after its first letter
+    /// (`dst/pdf/R/report.pdf`); names not starting with a letter or digit go to `#`
+    pub bucket_by_initial: bool,
+    /// Route into a folder the destination already has whose name differs
+    /// from the category only in case (`Invoices` for `invoices`)
+    pub merge_existing: bool,
+    /// Copy files into the organized tree and leave the originals untouched
+    pub copy_only: bool,
+    /// Link files into the organized tree instead of moving or copying them;
+    /// the originals stay in place. Takes precedence over `copy_only`.
+    pub link_mode: Option<LinkMode>,
+    /// Let copies (`copy_only` and moves across filesystems) get fresh
+    /// timestamps instead of the original modification/access times and permissions
+    pub no_preserve_times: bool,
+    /// Send files replaced by an overwrite, and the sources of cross-device
+    /// moves, to the OS trash instead of deleting them
+    pub use_trash: bool,
+    /// Compare every copy with its source (SHA-256) before the source is
+    /// deleted; a same-filesystem rename is never checked
+    pub verify: bool,
+    /// Revert every move of the run if a fatal error (see
+    /// `OrganizerError::is_fatal`) occurs; per-file errors are still skipped
+    pub transactional: bool,
+    /// After the run, delete source subfolders that were emptied by moving
+    /// their files out (never `src_dir` itself, never folders with files left)
+    pub remove_empty_dirs: bool,
+    /// Whether names on the destination filesystem compare case-insensitively
+    /// (`Report.PDF` == `report.pdf`); `None` probes `dst_dir` in `Organizer::new`,
+    /// or assumes the platform default in a dry run, which writes nothing
+    pub case_insensitive: Option<bool>,
+    /// Make moving files whose name matches the glob fail with the given
+    /// error instead of touching them, to exercise error handling and
+    /// rollback; a dry run moves nothing and so never fails
+    #[cfg(feature = "testing")]
+    pub simulate_errors: Vec<(String, SimulatedFailure)>,
+}
+
+/// Error `OrganizerConfig::simulate_errors` injects
+#[cfg(feature = "testing")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum SimulatedFailure {
+    /// A source file that can't be read or removed; skipped like any per-file failure
+    PermissionDenied,
+    /// Fatal, as a full destination
+    StorageFull,
+    /// Counted as in use, and retried with `retry_locked`
+    InUse,
+    /// An ordinary per-file failure
+    Other,
+}
+
+#[cfg(feature = "testing")]
+impl SimulatedFailure {
+    fn error(self, path: &Path) -> OrganizerError {
+        let io_error = |kind| io::Error::new(kind, "simulated");
+        match self {
+            SimulatedFailure::PermissionDenied => {
+                OrganizerError::io_at(path, io_error(io::ErrorKind::PermissionDenied))
+            }
+            SimulatedFailure::StorageFull => {
+                OrganizerError::Io(io_error(io::ErrorKind::StorageFull))
+            }
+            SimulatedFailure::InUse => OrganizerError::FileInUse(path.to_path_buf()),
+            SimulatedFailure::Other => {
+                OrganizerError::Other(format!("Simulated failure for {:?}", path))
+            }
+        }
+    }
+}
+
+impl OrganizerConfig {
+    /// `src_dir` followed by `extra_src_dirs`
+    pub fn src_dirs(&self) -> impl Iterator<Item = &Path> {
+        std::iter::once(self.src_dir.as_path())
+            .chain(self.extra_src_dirs.iter().map(PathBuf::as_path))
+    }
+
+    /// The source folder `path` lies in; the innermost one if sources are nested
+    pub fn source_of(&self, path: &Path) -> Option<&Path> {
+        self.src_dirs()
+            .filter(|root| path.starts_with(root))
+            .max_by_key(|root| root.components().count())
+    }
+}
+
+/// How `process_file` handles a target that already exists (or is
+/// reserved by another file of the same run)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum ConflictStrategy {
+    /// Append `_(1)`, `_(2)`, … to the file name
+    #[default]
+    Rename,
+    /// Replace the existing file
+    Overwrite,
+    /// Leave the source where it is
+    Skip,
+    /// Skip when the existing file has the same content, rename otherwise
+    SkipIfIdentical,
+    /// Append a short content hash to the file name
+    HashSuffix,
+    /// Append the source's modification time (`report_2024-05-01T10-22-33.pdf`),
+    /// so versions sort chronologically
+    TimestampSuffix,
+}
+
+/// What to do with a target folder/file name the destination OS does not accept
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum InvalidNameStrategy {
+    /// Replace illegal characters with `_` and shorten overlong names
+    #[default]
+    Sanitize,
+    /// Fail the file with `OrganizerError::InvalidName`
+    Error,
+}
+
+/// Normalization form for `OrganizerConfig::normalize_unicode`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum UnicodeForm {
+    /// Composed, as Linux and Windows tools usually expect
+    Nfc,
+    /// Decomposed, as macOS volumes store names
+    Nfd,
+}
+
+/// Kind of link `link_mode` creates in the organized tree
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum LinkMode {
+    /// Same file under a second name; both must be on one filesystem
+    Hard,
+    /// Link pointing to the absolute path of the original
+    Symbolic,
+}
+
+/// What `organize` does with the folders found in a source folder
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TreatDirs {
+    /// Walk into every folder and move only the files
+    #[default]
+    SkipDirs,
+    /// Classify each folder directly in a source folder by its name and move
+    /// it whole, without walking into it. Folders no rule matches by name
+    /// are walked as usual; with `copy_only` or `link_mode` every folder is.
+    MoveTopLevelDirs,
+}
+
+/// What a planned move does to the destination, judged against what is on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+pub enum PlanAction {
+    /// The target name is free
+    Created,
+    /// An existing file has the name; the move gets a new one
+    Renamed,
+    /// An existing file is replaced
+    Overwritten,
+    /// The source stays where it is
+    Skipped,
+    /// Another file of the same run claims the name (nothing on disk yet)
+    Conflict,
+}
+
+/// One decision made by `plan()` (and `organize()`); in dry-run mode nothing is executed
+#[derive(Debug, Clone, Serialize)]
+pub struct PlannedMove {
+    pub from: PathBuf,
+    /// Final target, after conflict resolution; for `Skipped` the name that was taken
+    pub to: PathBuf,
+    /// Category returned by the rule engine
+    pub rule_matched: String,
+    /// Rule pattern or engine that chose the category, "fallback" if none had an opinion
+    pub rule: Option<String>,
+    pub action: PlanAction,
+    /// Source size in bytes when the plan was made
+    pub size: u64,
+}
+
+/// What `undo_all` would do with one recorded move, from `preview_undo_all`
+#[derive(Debug, Clone)]
+pub struct UndoAction {
+    pub moved: MovedFile,
+    /// `moved.to` is still there; if not, undo skips this move
+    pub destination_exists: bool,
+    /// Something else now sits at `moved.from`; the file is restored under
+    /// a numbered name next to it (only matters for moves, not copies)
+    pub origin_occupied: bool,
+}
+
+/// Summary of one `organize()` call
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct OrganizeReport {
+    /// Moves made (or, in dry-run mode, planned), sorted by source path.
+    /// Files left in place are included with `PlanAction::Skipped`
+    #[serde(skip)]
+    pub moves: Vec<PlannedMove>,
+    pub files_moved: usize,
+    pub bytes_moved: u64,
+    /// Files per category returned by the rule engine
+    pub per_category: BTreeMap<String, usize>,
+    /// Target names that were already taken, whatever `on_conflict` did about it
+    pub conflicts: usize,
+    /// Files left in place by the `Skip`/`SkipIfIdentical` strategies
+    pub skipped: usize,
+    /// Files left in place because another process had them open
+    pub in_use: usize,
+    /// Files left in place because a copy (`copy_only`, a move across
+    /// filesystems) would not fit into the free space of the destination
+    pub no_space: usize,
+    /// Files left in place because they already are in their target folder
+    /// (a second run over a folder that is its own destination)
+    pub already_organized: usize,
+    pub failed: usize,
+    /// Source folders deleted by `remove_empty_dirs`
+    pub dirs_removed: usize,
+    /// `max_files` files were moved and the remaining ones left in place
+    pub limit_reached: bool,
+    #[serde(rename = "elapsed_secs", serialize_with = "serialize_secs")]
+    pub elapsed: Duration,
+}
+
+fn serialize_secs<S: serde::Serializer>(d: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(d.as_secs_f64())
+}
+
+/// Snapshot passed to the progress callback after each file
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub processed: usize,
+    pub total: usize,
+    pub current_path: PathBuf,
+    /// While `current_path` is being copied (`copy_only`, moves across
+    /// filesystems): bytes written so far and the size of the file
+    pub copied: Option<(u64, u64)>,
+}
+
+pub type ProgressCallback = Box<dyn Fn(Progress) + Send + Sync>;
+
+/// Answer of the conflict prompt; the `*All` variants apply to every
+/// remaining conflict of the run without asking again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictChoice {
+    Overwrite,
+    Rename,
+    Skip,
+    OverwriteAll,
+    SkipAll,
+}
+
+/// Asked with `(source, taken target)` for every conflict, one call at a time
+pub type ConflictPrompt = Box<dyn Fn(&Path, &Path) -> ConflictChoice + Send + Sync>;
+
+/// A classified file whose target name has not been checked for conflicts yet
+struct Classified {
+    from: PathBuf,
+    target: PathBuf,
+    rule_matched: String,
+    rule: Option<String>,
+    size: u64,
+}
+
+/// Bookkeeping of one `plan_files` call
+#[derive(Default)]
+struct PlanContext {
+    /// Targets already claimed by the plan; nothing exists on disk for them
+    /// yet, so collisions within the run are detected through this set
+    reserved: HashSet<PathBuf>,
+    /// Strategy chosen with Overwrite-all/Skip-all
+    sticky_conflict: Option<ConflictStrategy>,
+    /// First fatal error in transactional mode; stops planning
+    fatal: Mutex<Option<OrganizerError>>,
+    in_use: AtomicUsize,
+    already_organized: AtomicUsize,
+}
+
+/// State shared by the workers while a plan is executed
+struct RunContext<'a> {
+    history: HistoryBatch<'a>,
+    moves: Mutex<Vec<PlannedMove>>,
+    /// Moves actually executed, kept for transactional rollback
+    performed: Mutex<Vec<MovedFile>>,
+    /// Folders the run created for its targets, removed again by a rollback
+    created_dirs: Mutex<Vec<PathBuf>>,
+    /// First fatal error in transactional mode; stops the run
+    fatal: Mutex<Option<OrganizerError>>,
+    processed: AtomicUsize,
+    in_use: AtomicUsize,
+    no_space: AtomicUsize,
+    /// When the next file may start under `max_rate`
+    next_transfer: Mutex<Option<Instant>>,
+    /// Entries of the plan, for progress reports
+    total: usize,
+    /// Files being moved or moved, held against `max_files`
+    claimed: AtomicUsize,
+    limit_reached: AtomicBool,
 }
 
 pub struct Organizer<R: RuleEngine + 'static> {
     config: OrganizerConfig,
     rules: Arc<R>,
     history: Arc<HistoryManager>,
+    /// Hashes of earlier runs, kept next to the history
+    hashes: HashCache,
     cancel: Arc<AtomicBool>,
-    last_error: Arc<Mutex<Option<OrganizerError>>>,
+    /// Set while the run is paused; workers wait before their next file
+    pause: Arc<AtomicBool>,
+    /// Per-file failures of the last `organize()` call, in completion order
+    errors: Mutex<Vec<(PathBuf, OrganizerError)>>,
+    progress: Option<ProgressCallback>,
+    conflict_prompt: Option<ConflictPrompt>,
 }
 
 impl<R: RuleEngine + 'static> Organizer<R> {
-    pub fn new(config: OrganizerConfig, rules: R, history: HistoryManager) -> Self {
+    pub fn new(mut config: OrganizerConfig, rules: R, history: HistoryManager) -> Self {
+        if config.case_insensitive.is_none() {
+            let insensitive = if config.dry_run {
+                platform_case_insensitive()
+            } else {
+                probe_case_insensitive(&config.dst_dir)
+            };
+            debug!(
+                "Destination {:?} case-insensitive: {}",
+                config.dst_dir, insensitive
+            );
+            config.case_insensitive = Some(insensitive);
+        }
         Self {
             config,
             rules: Arc::new(rules),
+            hashes: HashCache::load(history.path().with_file_name("hashes.json")),
             history: Arc::new(history),
             cancel: Arc::new(AtomicBool::new(false)),
-            last_error: Arc::new(Mutex::new(None)),
+            pause: Arc::new(AtomicBool::new(false)),
+            errors: Mutex::new(Vec::new()),
+            progress: None,
+            conflict_prompt: None,
         }
     }
 
+    /// Called after every file, from worker threads
+    pub fn set_progress_callback(&mut self, callback: ProgressCallback) {
+        self.progress = Some(callback);
+    }
+
+    /// Ask on every conflict instead of applying `on_conflict`
+    pub fn set_conflict_prompt(&mut self, prompt: ConflictPrompt) {
+        self.conflict_prompt = Some(prompt);
+    }
+
     pub fn cancel_handle(&self) -> Arc<AtomicBool> {
         self.cancel.clone()
     }
 
+    /// Stops the run after the files in progress; `resume` continues it
+    pub fn pause(&self) {
+        self.pause.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.pause.store(false, Ordering::Relaxed);
+    }
+
+    /// Flag behind `pause`/`resume`, for pausing from another thread
+    pub fn pause_handle(&self) -> Arc<AtomicBool> {
+        self.pause.clone()
+    }
+
+    /// True if the run was cancelled, after sleeping for as long as it is paused
+    fn cancelled(&self) -> bool {
+        while self.pause.load(Ordering::Relaxed) && !self.cancel.load(Ordering::Relaxed) {
+            std::thread::sleep(PAUSE_POLL);
+        }
+        self.cancel.load(Ordering::Relaxed)
+    }
+
+    /// Takes one of the `max_files` places for a file about to be moved;
+    /// false (and the limit is marked as reached) if none is left
+    fn claim(&self, run: &RunContext) -> bool {
+        let max = self.config.max_files.unwrap_or(usize::MAX);
+        let claimed = run
+            .claimed
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (n < max).then_some(n + 1)
+            })
+            .is_ok();
+        if !claimed {
+            run.limit_reached.store(true, Ordering::Relaxed);
+        }
+        claimed
+    }
+
+    /// Waits for the turn of a file of `bytes` under `max_rate`: each file
+    /// starts once the ones before it had their share of time. `false` if
+    /// the run was cancelled while waiting.
+    fn throttle(&self, bytes: u64, run: &RunContext) -> bool {
+        let Some(rate) = self.config.max_rate.filter(|&rate| rate > 0) else {
+            return true;
+        };
+        let start = {
+            let mut next = run.next_transfer.lock();
+            let now = Instant::now();
+            let start = next.map_or(now, |next| next.max(now));
+            *next = Some(start + Duration::from_secs_f64(bytes as f64 / rate as f64));
+            start
+        };
+        loop {
+            if self.cancelled() {
+                return false;
+            }
+            let now = Instant::now();
+            if now >= start {
+                return true;
+            }
+            std::thread::sleep((start - now).min(PAUSE_POLL));
+        }
+    }
+
+    fn conflict_template(&self) -> &str {
+        self.config
+            .conflict_template
+            .as_deref()
+            .unwrap_or(DEFAULT_CONFLICT_TEMPLATE)
+    }
+
+    pub fn config(&self) -> &OrganizerConfig {
+        &self.config
+    }
+
+    /// Most recent per-file failure
     pub fn last_error(&self) -> Option<String> {
-        self.last_error.lock().as_ref().map(|e| e.to_string())
+        self.errors.lock().last().map(|(_, e)| e.to_string())
     }
 
-    pub fn organize(&self) -> Result<(), OrganizerError> {
-        if self.config.src_dir == self.config.dst_dir {
-            warn!("Source and destination folders are the same, using nested subfolders.");
+    /// Files that failed during the last `organize()` call; the run
+    /// itself continues past them
+    pub fn errors(&self) -> MutexGuard<'_, Vec<(PathBuf, OrganizerError)>> {
+        self.errors.lock()
+    }
+
+    /// Returns the moves made (or, in dry-run mode, the moves that would be made)
+    /// together with run statistics
+    pub fn organize(&self) -> Result<OrganizeReport, OrganizerError> {
+        let started = Instant::now();
+        self.errors.lock().clear();
+        self.check_source()?;
+        let _lock = self.lock_run()?;
+        if self.config.src_dirs().any(|src| src == self.config.dst_dir) {
+            warn!(
+                "Source and destination folders are the same, using nested subfolders; \
+                 files already in theirs are left alone."
+            );
         }
 
-        for entry in WalkDir::new(&self.config.src_dir)
-            .into_iter()
-            .filter_map(|e| e.ok())
-        {
-            if self.cancel.load(Ordering::Relaxed) {
-                warn!("Operation cancelled by user");
+        let files = self.collect_files()?;
+        if self.cancel.load(Ordering::Relaxed) {
+            warn!("Operation cancelled by user");
+            return Ok(OrganizeReport::default());
+        }
+        self.plan_and_execute(&files, started)
+    }
+
+    /// Organizes just the given files (e.g. from watch mode) as one session.
+    /// Paths outside the source folders or rejected by the configured filters are ignored.
+    pub fn organize_paths(&self, paths: &[PathBuf]) -> Result<OrganizeReport, OrganizerError> {
+        let started = Instant::now();
+        self.errors.lock().clear();
+        let _lock = self.lock_run()?;
+        let filters = self
+            .config
+            .src_dirs()
+            .map(|root| Ok((root, self.path_filter(root)?)))
+            .collect::<Result<HashMap<_, _>, OrganizerError>>()?;
+        let files: Vec<PathBuf> = paths
+            .iter()
+            .filter(|p| self.accepts(&filters, p))
+            .cloned()
+            .collect();
+        self.plan_and_execute(&files, started)
+    }
+
+    /// Decides where every file of the source folders would go, conflicts included,
+    /// without moving anything or touching the history, whatever `dry_run`
+    /// says. Files that cannot be planned end up in `errors()`.
+    pub fn plan(&self) -> Result<Vec<PlannedMove>, OrganizerError> {
+        self.errors.lock().clear();
+        self.check_source()?;
+        let files = self.collect_files()?;
+        self.plan_files(&files, &mut PlanContext::default())
+    }
+
+    /// Executes a plan from `plan()` as one session. A `Created`/`Renamed`
+    /// target that was taken in the meantime fails with `DestinationExists`;
+    /// `Skipped` entries are only reported.
+    pub fn execute_plan(&self, plan: Vec<PlannedMove>) -> Result<OrganizeReport, OrganizerError> {
+        let started = Instant::now();
+        self.errors.lock().clear();
+        let _lock = self.lock_run()?;
+        self.execute(plan, 0, 0, started)
+    }
+
+    fn plan_and_execute(
+        &self,
+        files: &[PathBuf],
+        started: Instant,
+    ) -> Result<OrganizeReport, OrganizerError> {
+        let mut planning = PlanContext::default();
+        let plan = self.plan_files(files, &mut planning)?;
+        self.execute(
+            plan,
+            planning.in_use.into_inner(),
+            planning.already_organized.into_inner(),
+            started,
+        )
+    }
+
+    fn thread_pool(&self) -> Result<rayon::ThreadPool, OrganizerError> {
+        let mut pool = rayon::ThreadPoolBuilder::new();
+        if let Some(threads) = self.config.threads {
+            pool = pool.num_threads(threads);
+        }
+        pool.build()
+            .map_err(|e| OrganizerError::Other(format!("Cannot start worker threads: {}", e)))
+    }
+
+    /// Plans `files`; the result is sorted by source path
+    fn plan_files(
+        &self,
+        files: &[PathBuf],
+        planning: &mut PlanContext,
+    ) -> Result<Vec<PlannedMove>, OrganizerError> {
+        let classified = Mutex::new(Vec::with_capacity(files.len()));
+        let _ = self.thread_pool()?.install(|| {
+            files.par_iter().try_for_each(|path| {
+                if self.cancelled() {
+                    return Err(());
+                }
+                match self.classify_file(path) {
+                    Ok(None) => {
+                        debug!("Left folder {:?} in place: no rule matches its name", path);
+                        Ok(())
+                    }
+                    Ok(Some(file)) if !self.wants_category(&file.rule_matched) => {
+                        debug!(
+                            "Left {:?} in place: category {:?} not selected",
+                            path, file.rule_matched
+                        );
+                        Ok(())
+                    }
+                    Ok(Some(file)) if self.is_in_target_dir(&file) => {
+                        info!("Unchanged: {:?} is already organized", path);
+                        planning.already_organized.fetch_add(1, Ordering::Relaxed);
+                        Ok(())
+                    }
+                    Ok(Some(file)) => {
+                        classified.lock().push(file);
+                        Ok(())
+                    }
+                    Err(e) => self.plan_failure(path, e, planning),
+                }
+            })
+        });
+
+        // Classifying reads the files and runs in parallel; names are then
+        // resolved one file at a time in source order, so that `a_(1)` goes
+        // to the same file whichever worker finished first
+        let mut classified = classified.into_inner();
+        classified.sort_by(|a, b| a.from.cmp(&b.from));
+        let mut plan = Vec::with_capacity(classified.len());
+        for file in classified {
+            if planning.fatal.get_mut().is_some() || self.cancel.load(Ordering::Relaxed) {
                 break;
             }
-            let path = entry.path();
-            if path.is_dir() {
-                continue;
+            let from = file.from.clone();
+            match self.resolve_target(file, planning) {
+                Ok(planned) => plan.push(planned),
+                Err(e) => {
+                    let _ = self.plan_failure(&from, e, planning);
+                }
             }
+        }
+        if let Some(fatal) = planning.fatal.get_mut().take() {
+            return Err(fatal);
+        }
+        Ok(plan)
+    }
 
-            if let Err(e) = self.process_file(path) {
+    /// Records a file that could not be planned; `Err` stops planning
+    fn plan_failure(
+        &self,
+        path: &Path,
+        e: OrganizerError,
+        planning: &PlanContext,
+    ) -> Result<(), ()> {
+        match e {
+            OrganizerError::FileInUse(_) => {
+                warn!("Skipped {:?}: in use by another process", path);
+                planning.in_use.fetch_add(1, Ordering::Relaxed);
+            }
+            e if self.config.transactional && e.is_fatal() => {
+                error!("Fatal error on {:?}: {}", path, e);
+                planning.fatal.lock().get_or_insert(e);
+                return Err(());
+            }
+            e => {
                 error!("Failed to process {:?}: {}", path, e);
-                *self.last_error.lock() = Some(e);
+                self.errors.lock().push((path.to_path_buf(), e));
+            }
+        }
+        Ok(())
+    }
+
+    fn execute(
+        &self,
+        plan: Vec<PlannedMove>,
+        in_use: usize,
+        already_organized: usize,
+        started: Instant,
+    ) -> Result<OrganizeReport, OrganizerError> {
+        let pool = self.thread_pool()?;
+        let run = RunContext {
+            history: self.history.begin_batch()?,
+            moves: Mutex::new(Vec::new()),
+            performed: Mutex::new(Vec::new()),
+            created_dirs: Mutex::new(Vec::new()),
+            fatal: Mutex::new(None),
+            processed: AtomicUsize::new(0),
+            in_use: AtomicUsize::new(in_use),
+            no_space: AtomicUsize::new(0),
+            next_transfer: Mutex::new(None),
+            total: plan.len(),
+            claimed: AtomicUsize::new(0),
+            limit_reached: AtomicBool::new(false),
+        };
+        let total = plan.len();
+        let finished = pool.install(|| {
+            plan.par_iter().try_for_each(|planned| {
+                if self.cancelled() {
+                    return Err(());
+                }
+                let counted = planned.action != PlanAction::Skipped;
+                if counted && !self.claim(&run) {
+                    debug!("Left {:?} in place: file limit reached", planned.from);
+                    return Ok(());
+                }
+                if counted && !self.throttle(planned.size, &run) {
+                    return Err(());
+                }
+                let path = &planned.from;
+                let result = self.execute_move(planned, &run);
+                if counted && result.is_err() {
+                    // a failed file doesn't use up the limit
+                    run.claimed.fetch_sub(1, Ordering::SeqCst);
+                }
+                match result {
+                    Ok(()) => run.moves.lock().push(planned.clone()),
+                    Err(OrganizerError::CopyCancelled(_)) => return Err(()),
+                    Err(OrganizerError::FileInUse(_)) => {
+                        warn!("Skipped {:?}: in use by another process", path);
+                        run.in_use.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e @ OrganizerError::InsufficientSpace { .. }) => {
+                        warn!("Skipped: {}", e);
+                        run.no_space.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Err(e) if self.config.transactional && e.is_fatal() => {
+                        error!("Fatal error on {:?}: {}", path, e);
+                        run.fatal.lock().get_or_insert(e);
+                        return Err(());
+                    }
+                    Err(e) => {
+                        error!("Failed to process {:?}: {}", path, e);
+                        self.errors.lock().push((path.clone(), e));
+                    }
+                }
+                if let Some(callback) = &self.progress {
+                    callback(Progress {
+                        processed: run.processed.fetch_add(1, Ordering::Relaxed) + 1,
+                        total,
+                        current_path: path.clone(),
+                        copied: None,
+                    });
+                }
+                Ok(())
+            })
+        });
+
+        let session_id = run.history.session_id();
+        if let Some(fatal) = run.fatal.into_inner() {
+            run.history.commit()?;
+            self.rollback(run.performed.into_inner(), run.created_dirs.into_inner());
+            self.history.take_session(session_id)?;
+            return Err(fatal);
+        }
+        if finished.is_err() {
+            warn!("Operation cancelled by user");
+        }
+        let limit_reached = run.limit_reached.into_inner();
+        if limit_reached {
+            warn!(
+                "Stopped after {} file(s): file limit reached",
+                run.claimed.load(Ordering::SeqCst)
+            );
+        }
+        run.history.commit()?;
+        // a dry run leaves the disk untouched, cache included
+        if !self.config.dry_run {
+            if let Err(e) = self.hashes.save() {
+                warn!("Cannot save hash cache: {}", e);
+            }
+        }
+        if !run.performed.lock().is_empty() {
+            // standard redo semantics: a new run invalidates what was undone
+            self.history.clear_redo()?;
+        }
+
+        let mut dirs_removed = 0;
+        if self.config.remove_empty_dirs && !self.config.dry_run {
+            let removed = self.remove_empty_dirs(&run.performed.into_inner());
+            dirs_removed = removed.len();
+            let now = Utc::now();
+            self.history.record_removed_dirs(
+                removed
+                    .into_iter()
+                    .map(|path| RemovedDir {
+                        path,
+                        time: now,
+                        session_id,
+                    })
+                    .collect(),
+            )?;
+        }
+
+        let mut moves = run.moves.into_inner();
+        moves.sort_by(|a, b| a.from.cmp(&b.from));
+        let mut per_category = BTreeMap::new();
+        let mut bytes_moved = 0;
+        for mov in moves.iter().filter(|m| m.action != PlanAction::Skipped) {
+            *per_category.entry(mov.rule_matched.clone()).or_default() += 1;
+            bytes_moved += mov.size;
+        }
+        Ok(OrganizeReport {
+            files_moved: per_category.values().sum(),
+            bytes_moved,
+            per_category,
+            conflicts: moves
+                .iter()
+                .filter(|m| m.action != PlanAction::Created)
+                .count(),
+            skipped: moves
+                .iter()
+                .filter(|m| m.action == PlanAction::Skipped)
+                .count(),
+            moves,
+            in_use: run.in_use.into_inner(),
+            no_space: run.no_space.into_inner(),
+            already_organized,
+            failed: self.errors.lock().len(),
+            dirs_removed,
+            limit_reached,
+            elapsed: started.elapsed(),
+        })
+    }
+
+    /// Deletes the source folders left empty by `performed`, deepest first.
+    /// Only ancestors of moved files are considered, so folders that were
+    /// already empty, or still hold skipped (e.g. hidden) files, stay.
+    fn remove_empty_dirs(&self, performed: &[MovedFile]) -> Vec<PathBuf> {
+        let mut candidates = HashSet::new();
+        for mov in performed.iter().filter(|m| m.kind == TransferKind::Move) {
+            let Some(src_dir) = self.config.source_of(&mov.from) else {
+                continue;
+            };
+            for dir in mov.from.ancestors().skip(1) {
+                if dir == src_dir || !dir.starts_with(src_dir) {
+                    break;
+                }
+                candidates.insert(dir.to_path_buf());
+            }
+        }
+        let mut candidates: Vec<PathBuf> = candidates.into_iter().collect();
+        candidates.sort_by_key(|d| std::cmp::Reverse(d.components().count()));
+
+        let mut removed = Vec::new();
+        for dir in candidates {
+            let empty = fs::read_dir(&dir).is_ok_and(|mut entries| entries.next().is_none());
+            if !empty {
+                continue;
+            }
+            match fs::remove_dir(&dir) {
+                Ok(()) => {
+                    info!("Removed empty folder {:?}", dir);
+                    removed.push(dir);
+                }
+                Err(e) => warn!("Could not remove empty folder {:?}: {}", dir, e),
+            }
+        }
+        removed
+    }
+
+    /// Reverts the moves of an aborted transactional run, newest first, then
+    /// removes the folders it created that are empty again
+    fn rollback(&self, performed: Vec<MovedFile>, mut created_dirs: Vec<PathBuf>) {
+        warn!("Rolling back {} move(s)", performed.len());
+        for mov in performed.iter().rev() {
+            if let Err(e) = revert(mov, self.conflict_template()) {
+                error!("Rollback failed for {:?}: {}", mov.to, e);
+            }
+        }
+        // workers may have made the same folder; deepest first, so a parent
+        // is empty by the time it is reached
+        created_dirs.sort();
+        created_dirs.dedup();
+        created_dirs.sort_by_key(|dir| std::cmp::Reverse(dir.components().count()));
+        for dir in created_dirs {
+            if let Err(e) = fs::remove_dir(&dir) {
+                debug!("Kept folder {:?}: {}", dir, e);
+            }
+        }
+    }
+
+    /// Walks every source folder applying depth, hidden and glob filters.
+    /// Collected up front: with src == dst a lazy walk would revisit
+    /// files that were just moved into new category folders.
+    fn collect_files(&self) -> Result<Vec<PathBuf>, OrganizerError> {
+        let mut files = Vec::new();
+        for src_dir in self.config.src_dirs() {
+            files.extend(self.collect_from(src_dir)?);
+        }
+        if !self.config.extra_src_dirs.is_empty() {
+            // a source nested in another one is walked twice
+            files.sort();
+            files.dedup();
+        }
+        Ok(files)
+    }
+
+    fn collect_from(&self, src_dir: &Path) -> Result<Vec<PathBuf>, OrganizerError> {
+        let mut walker = WalkDir::new(src_dir);
+        if let Some(depth) = self.config.max_depth {
+            walker = walker.max_depth(depth);
+        }
+        let filter = self.path_filter(src_dir)?;
+        let rel = |p: &Path| p.strip_prefix(src_dir).unwrap_or(p).to_path_buf();
+
+        let include_hidden = self.config.include_hidden;
+        let whole_dirs = self.config.treat_dirs == TreatDirs::MoveTopLevelDirs
+            && self.transfer_kind() == TransferKind::Move;
+        let mut files = Vec::new();
+        let mut entries = walker.into_iter().filter_entry(|e| {
+            // the root itself is never skipped, even if it's e.g. `~/.cache`
+            if e.depth() == 0 {
+                return true;
+            }
+            if !include_hidden && is_hidden(e.path()) {
+                return false;
+            }
+            !(e.file_type().is_dir() && filter.excludes_dir(&rel(e.path())))
+        });
+        while let Some(entry) = entries.next() {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    let path = e.path().unwrap_or(src_dir).to_path_buf();
+                    error!("Cannot read {:?}: {}", path, e);
+                    self.errors.lock().push((path, io::Error::from(e).into()));
+                    continue;
+                }
+            };
+            if self.cancelled() {
+                break;
+            }
+            // deeper down, a folder moved whole by an earlier run (src == dst)
+            // is collected too, to be counted as already organized
+            if whole_dirs
+                && entry.file_type().is_dir()
+                && self.moves_whole(entry.path())
+                && (entry.depth() == 1 || self.is_organized_dir(entry.path()))
+            {
+                entries.skip_current_dir();
+                files.push(entry.into_path());
+                continue;
             }
+            if !entry.file_type().is_dir()
+                && filter.allows_file(&rel(entry.path()))
+                && !self.is_history_file(entry.path())
+                && !self.is_partial(entry.path())
+                && !self.is_recent(entry.path())
+                && self.matches_metadata(entry.path())
+            {
+                files.push(entry.into_path());
+            }
+        }
+        Ok(files)
+    }
+
+    /// Whether the top-level folder `dir` is moved as a unit: a rule matches
+    /// its name, and it holds neither the destination nor the history
+    fn moves_whole(&self, dir: &Path) -> bool {
+        let absolute = |p: &Path| std::path::absolute(p).unwrap_or_else(|_| p.to_path_buf());
+        let holds = |inner: &Path| {
+            absolute(inner).starts_with(absolute(dir))
+                || match (fs::canonicalize(inner), fs::canonicalize(dir)) {
+                    (Ok(inner), Ok(dir)) => inner.starts_with(dir),
+                    _ => false,
+                }
+        };
+        if holds(&self.config.dst_dir) || holds(self.history.path()) {
+            debug!(
+                "Walking into {:?}: it holds the destination or the history",
+                dir
+            );
+            return false;
+        }
+        let matched = FileMeta::from_path(dir)
+            .map(|meta| meta.with_source(self.config.source_of(dir).unwrap_or(dir)))
+            .and_then(|meta| self.rules.try_classify_target(dir, &meta));
+        match matched {
+            Ok(matched) => matched.is_some(),
+            Err(e) => {
+                debug!("Walking into {:?}: cannot classify it ({})", dir, e);
+                false
+            }
+        }
+    }
+
+    fn is_organized_dir(&self, dir: &Path) -> bool {
+        matches!(self.classify_file(dir), Ok(Some(file)) if self.is_in_target_dir(&file))
+    }
+
+    /// Fails the whole run if a source folder is missing, not a folder or
+    /// unreadable; anything below it only fails the affected files
+    fn check_source(&self) -> Result<(), OrganizerError> {
+        for src_dir in self.config.src_dirs() {
+            let unavailable = |source: io::Error| OrganizerError::SourceUnavailable {
+                path: src_dir.to_path_buf(),
+                source,
+            };
+            if !fs::metadata(src_dir).map_err(unavailable)?.is_dir() {
+                return Err(unavailable(io::ErrorKind::NotADirectory.into()));
+            }
+            fs::read_dir(src_dir).map_err(unavailable)?;
         }
         Ok(())
     }
 
-    fn process_file(&self, path: &Path) -> Result<(), OrganizerError> {
-        let rel_path = path.strip_prefix(&self.config.src_dir).unwrap_or(path);
-        let target_subdir = self.rules.classify(path);
-        let target_dir = self.config.dst_dir.join(target_subdir);
-        fs::create_dir_all(&target_dir)?;
+    /// Globs of the config plus the ignore files of `src_dir`
+    fn path_filter(&self, src_dir: &Path) -> Result<PathFilter, OrganizerError> {
+        PathFilter::new(&self.config.include, &self.config.exclude)?
+            .with_ignore_files(src_dir, self.config.respect_gitignore)
+    }
 
+    /// Same checks as `collect_files`, for a single path
+    fn accepts(&self, filters: &HashMap<&Path, PathFilter>, path: &Path) -> bool {
+        let Some(src_dir) = self.config.source_of(path) else {
+            return false;
+        };
+        let (Ok(rel), Some(filter)) = (path.strip_prefix(src_dir), filters.get(src_dir)) else {
+            return false;
+        };
+        if !path.is_file() || self.is_history_file(path) {
+            return false;
+        }
+        let depth = rel.components().count();
+        if self.config.max_depth.is_some_and(|max| depth > max) {
+            return false;
+        }
+        let mut prefix = PathBuf::new();
+        for (i, component) in rel.components().enumerate() {
+            prefix.push(component);
+            if !self.config.include_hidden && is_hidden(&src_dir.join(&prefix)) {
+                return false;
+            }
+            if i + 1 < depth && filter.excludes_dir(&prefix) {
+                return false;
+            }
+        }
+        filter.allows_file(rel) && !self.is_partial(path) && self.matches_metadata(path)
+    }
+
+    /// Whether `path` has one of the extensions of downloads in progress
+    fn is_partial(&self, path: &Path) -> bool {
+        let partial = path.extension().and_then(OsStr::to_str).is_some_and(|ext| {
+            match &self.config.partial_extensions {
+                Some(list) => list
+                    .iter()
+                    .any(|p| p.trim_start_matches('.').eq_ignore_ascii_case(ext)),
+                None => DEFAULT_PARTIAL_EXTENSIONS
+                    .iter()
+                    .any(|p| p.eq_ignore_ascii_case(ext)),
+            }
+        });
+        if partial {
+            info!("Skipped {:?}: download in progress", path);
+        }
+        partial
+    }
+
+    /// Whether `path` was modified within `min_age` and may still be written.
+    /// Not checked in watch mode, which waits for the size to settle instead;
+    /// a modification time that can't be read or lies in the future holds nothing back.
+    fn is_recent(&self, path: &Path) -> bool {
+        let min_age = self.config.min_age.unwrap_or(DEFAULT_MIN_AGE);
+        if min_age.is_zero() {
+            return false;
+        }
+        let recent = fs::metadata(path)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(|modified| SystemTime::now().duration_since(modified).ok())
+            .is_some_and(|age| age < min_age);
+        if recent {
+            info!("Skipped {:?}: modified less than {:?} ago", path, min_age);
+        }
+        recent
+    }
+
+    /// Size and age filters, checked during the walk so that skipped files
+    /// are never classified. While `newer_than`/`older_than` is set, a file
+    /// whose modification time can't be read stays put.
+    fn matches_metadata(&self, path: &Path) -> bool {
+        let config = &self.config;
+        let by_size = config.min_size.is_some() || config.max_size.is_some() || config.skip_empty;
+        let by_age = config.newer_than.is_some() || config.older_than.is_some();
+        if !by_size && !by_age {
+            return true;
+        }
+        let meta = match fs::metadata(path) {
+            Ok(meta) => meta,
+            Err(e) => {
+                debug!("Skipped {:?}: {}", path, e);
+                return false;
+            }
+        };
+        let size = meta.len();
+        if (config.skip_empty && size == 0)
+            || config.min_size.is_some_and(|min| size < min)
+            || config.max_size.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+        if !by_age {
+            return true;
+        }
+        let modified = match meta.modified() {
+            Ok(modified) => modified,
+            Err(e) => {
+                debug!("Skipped {:?}: no modification time ({})", path, e);
+                return false;
+            }
+        };
+        // modified in the future counts as just modified
+        let age = SystemTime::now()
+            .duration_since(modified)
+            .unwrap_or(Duration::ZERO);
+        config.newer_than.is_none_or(|max| age <= max)
+            && config.older_than.is_none_or(|min| age > min)
+    }
+
+    /// The history and lock files may be kept inside the source folder; they must stay put
+    fn is_history_file(&self, path: &Path) -> bool {
+        [self.history.path().to_path_buf(), self.lock_path()]
+            .iter()
+            .any(|own| {
+                path.file_name() == own.file_name()
+                    && match (fs::canonicalize(path), fs::canonicalize(own)) {
+                        (Ok(a), Ok(b)) => a == b,
+                        _ => false,
+                    }
+            })
+    }
+
+    /// `lock` next to the history file — runs sharing a history exclude each other
+    fn lock_path(&self) -> PathBuf {
+        self.history.path().with_file_name("lock")
+    }
+
+    /// Dry runs change nothing and don't take the lock
+    fn lock_run(&self) -> Result<Option<RunLock>, OrganizerError> {
+        if self.config.dry_run {
+            return Ok(None);
+        }
+        RunLock::acquire(&self.lock_path(), self.config.wait_for_lock).map(Some)
+    }
+
+    /// Applies `normalize_unicode` and `on_invalid_name` to one folder/file
+    /// name of the target path; names that are not UTF-8 are left alone
+    fn clean_name(&self, name: &OsStr) -> Result<OsString, OrganizerError> {
+        let platform = Platform::current();
+        let Some(text) = name.to_str() else {
+            return Ok(name.to_owned());
+        };
+        let normalized: String = match self.config.normalize_unicode {
+            Some(UnicodeForm::Nfc) => text.nfc().collect(),
+            Some(UnicodeForm::Nfd) => text.nfd().collect(),
+            None => text.to_string(),
+        };
+        let text = normalized.as_str();
+        let Some(reason) = invalid_reason(text, platform) else {
+            return Ok(text.into());
+        };
+        match self.config.on_invalid_name {
+            InvalidNameStrategy::Error => Err(OrganizerError::InvalidName {
+                name: text.to_string(),
+                reason,
+            }),
+            InvalidNameStrategy::Sanitize => {
+                let clean = sanitize(text, platform);
+                debug!("Using {:?} instead of {:?}, which {}", clean, text, reason);
+                Ok(clean.into())
+            }
+        }
+    }
+
+    fn copy_options(&self) -> CopyOptions {
+        CopyOptions {
+            preserve_times: !self.config.no_preserve_times,
+            verify: self.config.verify,
+            use_trash: self.config.use_trash,
+        }
+    }
+
+    /// With `merge_existing`, the name of a folder in `parent` that equals
+    /// `name` ignoring case (the first in sort order if there are several);
+    /// otherwise, or without such a folder, `name`
+    fn existing_dir_name(&self, parent: &Path, name: OsString) -> OsString {
+        if !self.config.merge_existing || parent.join(&name).is_dir() {
+            return name;
+        }
+        let (Some(wanted), Ok(entries)) =
+            (name.to_str().map(str::to_lowercase), fs::read_dir(parent))
+        else {
+            return name;
+        };
+        entries
+            .flatten()
+            .filter(|entry| entry.file_type().is_ok_and(|t| t.is_dir()))
+            .map(|entry| entry.file_name())
+            .filter(|existing| {
+                existing
+                    .to_str()
+                    .is_some_and(|e| e.to_lowercase() == wanted)
+            })
+            .min()
+            .unwrap_or(name)
+    }
+
+    /// Key under which `path` is reserved/locked, so that names differing
+    /// only in case collide on a case-insensitive destination
+    fn fold_case(&self, path: &Path) -> PathBuf {
+        if self.config.case_insensitive.unwrap_or(false) {
+            PathBuf::from(path.to_string_lossy().to_lowercase())
+        } else {
+            path.to_path_buf()
+        }
+    }
+
+    fn wants_category(&self, category: &str) -> bool {
+        let only = &self.config.only_categories;
+        only.is_empty() || only.iter().any(|c| c.eq_ignore_ascii_case(category))
+    }
+
+    /// Whether `file` already sits in the folder it would be moved to. Paths
+    /// spelled differently (`dst/./jpg`, a symlinked `dst`) are compared
+    /// canonicalized, so a file is never moved or overwritten onto itself.
+    /// A folder that would go into itself is the category folder already.
+    fn is_in_target_dir(&self, file: &Classified) -> bool {
+        if file.target.starts_with(&file.from) {
+            return true;
+        }
+        if self.config.link_mode.is_some() && links_to(&file.target, &file.from) {
+            return true;
+        }
+        let (Some(current), Some(target)) = (file.from.parent(), file.target.parent()) else {
+            return false;
+        };
+        if self.fold_case(current) == self.fold_case(target) {
+            return true;
+        }
+        match (fs::canonicalize(current), fs::canonicalize(target)) {
+            (Ok(current), Ok(target)) => self.fold_case(&current) == self.fold_case(&target),
+            _ => false,
+        }
+    }
+
+    /// Asks the rule engine for the target folder of `path`. A folder (see
+    /// `TreatDirs::MoveTopLevelDirs`) only gets one if a rule matches its name.
+    fn classify_file(&self, path: &Path) -> Result<Option<Classified>, OrganizerError> {
+        let is_dir = fs::symlink_metadata(path).is_ok_and(|meta| meta.is_dir());
+        let src_dir = self.config.source_of(path);
+        let rel_path = src_dir
+            .and_then(|src_dir| path.strip_prefix(src_dir).ok())
+            .unwrap_or(path);
+        let mut meta = FileMeta::from_path(path)?;
+        if let Some(src_dir) = src_dir {
+            meta = meta.with_source(src_dir);
+        }
+        let classified = if is_dir {
+            self.rules.try_classify_target(path, &meta)?
+        } else {
+            Some(self.rules.classify_target(path, &meta)?)
+        };
+        let Some(Classification {
+            root,
+            subdir: target_subdir,
+            rule,
+        }) = classified
+        else {
+            return Ok(None);
+        };
+        debug!("Classified {:?} as {:?}", path, target_subdir);
+        let root = root.unwrap_or_else(|| self.config.dst_dir.clone());
+        let mut target_dir = root.clone();
+        for component in normalize_target(&target_subdir)?.components() {
+            let name = self.clean_name(component.as_os_str())?;
+            let name = self.existing_dir_name(&target_dir, name);
+            target_dir.push(name);
+        }
+        if self.config.preserve_tree {
+            if let Some(parent) = rel_path.parent() {
+                target_dir.push(parent);
+            }
+        }
         let file_name = rel_path.file_name().ok_or_else(|| {
             OrganizerError::Other(format!("Cannot extract filename from {:?}", rel_path))
         })?;
+        let file_name = self.clean_name(file_name)?;
+        if self.config.bucket_by_initial {
+            target_dir.push(initial_bucket(&file_name));
+        }
+        if let Some(file) = file_in_the_way(&root, &target_dir) {
+            return Err(OrganizerError::FileInTheWay(file));
+        }
 
-        let mut target_path = target_dir.join(file_name);
+        Ok(Some(Classified {
+            from: path.to_path_buf(),
+            target: target_dir.join(file_name),
+            rule_matched: target_subdir,
+            rule,
+            size: if is_dir { dir_size(path) } else { meta.size },
+        }))
+    }
 
-        // resolve conflicts
-        if target_path.exists() && !self.config.overwrite {
-            target_path = self.resolve_conflict(&target_path)?;
+    /// Resolves the target name of `file` against the disk and the files
+    /// planned before it. Files left in place because of a conflict come
+    /// back as `PlanAction::Skipped`.
+    fn resolve_target(
+        &self,
+        file: Classified,
+        planning: &mut PlanContext,
+    ) -> Result<PlannedMove, OrganizerError> {
+        let path = file.from.as_path();
+        let mut target_path = file.target.clone();
+        let reserved = &planning.reserved;
+        let taken = |p: &Path| p.exists() || reserved.contains(&self.fold_case(p));
+        let planned = |to: PathBuf, action: PlanAction| PlannedMove {
+            from: file.from.clone(),
+            to,
+            rule_matched: file.rule_matched.clone(),
+            rule: file.rule.clone(),
+            action,
+            size: file.size,
+        };
+        let mut action = PlanAction::Created;
+        if taken(&target_path) {
+            let on_disk = target_path.exists();
+            action = if on_disk {
+                PlanAction::Renamed
+            } else {
+                PlanAction::Conflict
+            };
+            let mut strategy =
+                self.conflict_strategy(path, &target_path, &mut planning.sticky_conflict);
+            // a folder is never merged into or replaced by another one, and has no single hash
+            let is_dir = fs::symlink_metadata(path).is_ok_and(|meta| meta.is_dir());
+            if is_dir
+                && matches!(
+                    strategy,
+                    ConflictStrategy::Overwrite
+                        | ConflictStrategy::SkipIfIdentical
+                        | ConflictStrategy::HashSuffix
+                )
+            {
+                debug!("Renaming folder {:?} instead of {:?}", path, strategy);
+                strategy = ConflictStrategy::Rename;
+            }
+            match strategy {
+                ConflictStrategy::Rename => {
+                    target_path = resolve_conflict(&target_path, self.conflict_template(), taken)?;
+                }
+                ConflictStrategy::Overwrite => {
+                    debug!("Overwriting {:?}", target_path);
+                    if on_disk {
+                        action = PlanAction::Overwritten;
+                    }
+                }
+                ConflictStrategy::Skip => {
+                    info!("Skipped {:?}: {:?} already exists", path, target_path);
+                    return Ok(planned(target_path, PlanAction::Skipped));
+                }
+                ConflictStrategy::SkipIfIdentical => {
+                    if on_disk && self.same_content(path, &target_path)? {
+                        info!("Skipped {:?}: identical to {:?}", path, target_path);
+                        return Ok(planned(target_path, PlanAction::Skipped));
+                    }
+                    target_path = resolve_conflict(&target_path, self.conflict_template(), taken)?;
+                }
+                ConflictStrategy::HashSuffix => {
+                    let hash = self.hashes.hash(path)?;
+                    let hashed = with_suffix(&target_path, &hash[..8]);
+                    target_path = if taken(&hashed) {
+                        resolve_conflict(&hashed, self.conflict_template(), taken)?
+                    } else {
+                        hashed
+                    };
+                }
+                ConflictStrategy::TimestampSuffix => {
+                    let modified = fs::metadata(path)
+                        .and_then(|meta| meta.modified())
+                        .map_or_else(|_| Local::now(), DateTime::<Local>::from);
+                    let stamped = with_suffix(
+                        &target_path,
+                        &modified.format("%Y-%m-%dT%H-%M-%S").to_string(),
+                    );
+                    target_path = if taken(&stamped) {
+                        resolve_conflict(&stamped, self.conflict_template(), taken)?
+                    } else {
+                        stamped
+                    };
+                }
+            }
         }
+        let planned = planned(target_path, action);
+        planning.reserved.insert(self.fold_case(&planned.to));
+        Ok(planned)
+    }
 
-        info!("Move: {:?} -> {:?}", path, target_path);
+    /// The error `simulate_errors` gives for `path`, if its name matches a pattern
+    #[cfg(feature = "testing")]
+    fn simulated_error(&self, path: &Path) -> Option<OrganizerError> {
+        let name = path.file_name()?;
+        self.config
+            .simulate_errors
+            .iter()
+            .find(|(pattern, _)| {
+                globset::Glob::new(pattern).is_ok_and(|glob| glob.compile_matcher().is_match(name))
+            })
+            .map(|(_, failure)| failure.error(path))
+    }
 
-        if !self.config.dry_run {
-            move_file(path, &target_path)?;
-            self.history.push(MovedFile {
-                from: path.to_path_buf(),
-                to: target_path.clone(),
-                time: Utc::now(),
-            })?;
+    /// Fails like `simulated_error` says, retrying an in-use file as `retry_locked` asks
+    #[cfg(feature = "testing")]
+    fn fail_simulated(&self, path: &Path) -> Result<(), OrganizerError> {
+        let mut retries = 0;
+        while let Some(e) = self.simulated_error(path) {
+            match e {
+                OrganizerError::FileInUse(_) if retries < self.config.retry_locked => {
+                    retries += 1;
+                    std::thread::sleep(RETRY_LOCKED_DELAY);
+                }
+                e => return Err(e),
+            }
         }
         Ok(())
     }
 
-    fn resolve_conflict(&self, target: &Path) -> Result<PathBuf, OrganizerError> {
-        let stem = target
-            .file_stem()
-            .and_then(|s| s.to_str())
-            .unwrap_or("file");
-        let ext = target.extension().and_then(|s| s.to_str()).unwrap_or("");
-        for i in 1.. {
-            let candidate = if ext.is_empty() {
-                target.with_file_name(format!("{}_({})", stem, i))
-            } else {
-                target.with_file_name(format!("{}_({}).{}", stem, i, ext))
+    /// How `execute_move` gets files into the organized tree
+    fn transfer_kind(&self) -> TransferKind {
+        match self.config.link_mode {
+            Some(LinkMode::Hard) => TransferKind::HardLink,
+            Some(LinkMode::Symbolic) => TransferKind::Symlink,
+            None if self.config.copy_only => TransferKind::Copy,
+            None => TransferKind::Move,
+        }
+    }
+
+    /// Moves (copies, links) one planned file and records it in the history;
+    /// does nothing in dry-run mode or for `Skipped` entries
+    fn execute_move(&self, planned: &PlannedMove, run: &RunContext) -> Result<(), OrganizerError> {
+        if planned.action == PlanAction::Skipped {
+            return Ok(());
+        }
+        let (path, target_path) = (&planned.from, &planned.to);
+        let kind = self.transfer_kind();
+        if self.config.dry_run {
+            // the caller renders the plan from the report
+            debug!(
+                "{:?}: {:?} -> {:?} ({:?})",
+                kind, path, target_path, planned.action
+            );
+            return Ok(());
+        }
+        // before anything touches the disk, as a real failure of the file would
+        #[cfg(feature = "testing")]
+        self.fail_simulated(path)?;
+        // the plan only knew the disk as it was; don't replace what appeared since
+        if matches!(planned.action, PlanAction::Created | PlanAction::Renamed)
+            && target_path.exists()
+        {
+            return Err(OrganizerError::DestinationExists(target_path.clone()));
+        }
+        info!("{:?}: {:?} -> {:?}", kind, path, target_path);
+
+        // before the target folder is made, so a file that doesn't fit leaves none behind
+        if kind == TransferKind::Copy
+            || (kind == TransferKind::Move && !same_filesystem(path, target_path))
+        {
+            check_space(path, target_path)?;
+        }
+        if let Some(target_dir) = target_path.parent() {
+            let missing: Vec<PathBuf> = target_dir
+                .ancestors()
+                .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+                .map(Path::to_path_buf)
+                .collect();
+            // a rule's `target_root` may put `target_dir` outside `dst_dir`
+            let top = target_dir.ancestors().last().unwrap_or(target_dir);
+            fs::create_dir_all(target_dir).map_err(|e| match file_in_the_way(top, target_dir) {
+                Some(file) => OrganizerError::FileInTheWay(file),
+                None => OrganizerError::TargetDir {
+                    path: target_dir.to_path_buf(),
+                    source: e,
+                },
+            })?;
+            run.created_dirs.lock().extend(missing);
+        }
+        let replaced_trashed = self.config.use_trash && target_path.exists();
+        if replaced_trashed {
+            info!("Sending {:?} to the trash", target_path);
+            move_to_trash(target_path)?;
+        }
+        let report_copied = |copied| {
+            if let Some(callback) = &self.progress {
+                callback(Progress {
+                    processed: run.processed.load(Ordering::Relaxed),
+                    total: run.total,
+                    current_path: path.clone(),
+                    copied: Some((copied, planned.size)),
+                });
+            }
+        };
+        let watch = CopyWatch {
+            cancel: &self.cancel,
+            on_chunk: &report_copied,
+        };
+        let mut retries = 0;
+        loop {
+            let result = match kind {
+                TransferKind::Move => {
+                    move_file(path, target_path, self.copy_options(), Some(&watch))
+                }
+                TransferKind::Copy => {
+                    copy_file(path, target_path, self.copy_options(), Some(&watch))
+                }
+                TransferKind::HardLink | TransferKind::Symlink => {
+                    link_file(path, target_path, kind)
+                }
             };
-            if !candidate.exists() {
-                return Ok(candidate);
+            match result {
+                Ok(()) if kind == TransferKind::Move => {
+                    self.hashes.rename(path, target_path);
+                    break;
+                }
+                Err(OrganizerError::FileInUse(_)) if retries < self.config.retry_locked => {
+                    retries += 1;
+                    debug!(
+                        "{:?} is in use, retry {} in {:?}",
+                        path, retries, RETRY_LOCKED_DELAY
+                    );
+                    std::thread::sleep(RETRY_LOCKED_DELAY);
+                }
+                result => break result?,
             }
         }
-        Err(OrganizerError::Other(
-            "Unable to resolve name conflict".into(),
-        ))
+        let moved = MovedFile {
+            from: path.clone(),
+            to: target_path.clone(),
+            time: Utc::now(),
+            session_id: run.history.session_id(),
+            kind,
+            replaced_trashed,
+            rule_matched: planned.rule.clone(),
+        };
+        run.performed.lock().push(moved.clone());
+        run.history.push(moved)
+    }
+
+    /// Compares the files by size, then by their (cached) hashes
+    fn same_content(&self, a: &Path, b: &Path) -> io::Result<bool> {
+        if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+            return Ok(false);
+        }
+        Ok(self.hashes.hash(a)? == self.hashes.hash(b)?)
+    }
+
+    /// `on_conflict`, or the user's answer when a prompt is set
+    fn conflict_strategy(
+        &self,
+        from: &Path,
+        target: &Path,
+        sticky: &mut Option<ConflictStrategy>,
+    ) -> ConflictStrategy {
+        let Some(prompt) = &self.conflict_prompt else {
+            return self.config.on_conflict;
+        };
+        if let Some(strategy) = *sticky {
+            return strategy;
+        }
+        match prompt(from, target) {
+            ConflictChoice::Overwrite => ConflictStrategy::Overwrite,
+            ConflictChoice::Rename => ConflictStrategy::Rename,
+            ConflictChoice::Skip => ConflictStrategy::Skip,
+            ConflictChoice::OverwriteAll => *sticky.insert(ConflictStrategy::Overwrite),
+            ConflictChoice::SkipAll => *sticky.insert(ConflictStrategy::Skip),
+        }
     }
 
     pub fn undo_last(&self) -> Result<(), OrganizerError> {
-        if let Some(mov) = self.history.pop_last()? {
-            info!("Undo: {:?} -> {:?}", mov.to, mov.from);
-            if mov.to.exists() {
-                move_file(&mov.to, &mov.from)?;
-            } else {
-                warn!("Destination file missing: {:?}", mov.to);
+        let _lock = self.lock_run()?;
+        match self.history.pop_last()? {
+            Some(mov) => self.revert_all(vec![mov]),
+            None => {
+                warn!("Nothing to undo");
+                Ok(())
             }
-        } else {
-            warn!("Nothing to undo");
         }
-        Ok(())
     }
 
     pub fn undo_all(&self) -> Result<(), OrganizerError> {
-        let mut moves = self.history.take_all()?;
-        moves.reverse();
-        for mov in moves {
-            info!("Undo: {:?} -> {:?}", mov.to, mov.from);
-            if mov.to.exists() {
-                move_file(&mov.to, &mov.from)?;
-            } else {
-                warn!("Destination file missing: {:?}", mov.to);
+        let _lock = self.lock_run()?;
+        restore_dirs(self.history.take_removed_dirs(|_| true)?);
+        let moves = self.history.take_all()?;
+        self.revert_all(moves)
+    }
+
+    /// The reversals `undo_all` would make, newest first, without touching
+    /// the disk or the history
+    pub fn preview_undo_all(&self) -> Result<Vec<UndoAction>, OrganizerError> {
+        let mut moves = self.history.load()?.moves;
+        moves.sort_by_key(|m| std::cmp::Reverse(m.time));
+        Ok(moves
+            .into_iter()
+            .map(|moved| UndoAction {
+                destination_exists: fs::symlink_metadata(&moved.to).is_ok(),
+                origin_occupied: moved.from.exists(),
+                moved,
+            })
+            .collect())
+    }
+
+    /// Reverts only the moves made by one `organize()` run
+    pub fn undo_session(&self, session_id: u64) -> Result<(), OrganizerError> {
+        let _lock = self.lock_run()?;
+        restore_dirs(
+            self.history
+                .take_removed_dirs(|d| d.session_id == session_id)?,
+        );
+        let moves = self.history.take_session(session_id)?;
+        if moves.is_empty() {
+            warn!("Nothing to undo for session {}", session_id);
+        }
+        self.revert_all(moves)
+    }
+
+    /// Reverts every move made at or after `since`
+    pub fn undo_since(&self, since: DateTime<Utc>) -> Result<(), OrganizerError> {
+        let _lock = self.lock_run()?;
+        restore_dirs(self.history.take_removed_dirs(|d| d.time >= since)?);
+        let moves = self.history.take_matching(|m| m.time >= since)?;
+        if moves.is_empty() {
+            warn!("Nothing to undo since {}", since);
+        }
+        self.revert_all(moves)
+    }
+
+    /// Reverts moves made in `[start, end)`
+    pub fn undo_between(
+        &self,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<(), OrganizerError> {
+        let _lock = self.lock_run()?;
+        restore_dirs(
+            self.history
+                .take_removed_dirs(|d| d.time >= start && d.time < end)?,
+        );
+        let moves = self
+            .history
+            .take_matching(|m| m.time >= start && m.time < end)?;
+        if moves.is_empty() {
+            warn!("Nothing to undo between {} and {}", start, end);
+        }
+        self.revert_all(moves)
+    }
+
+    /// Reverts the latest move of one file, found by where it came from or
+    /// where it went; the rest of the history stays as it is
+    pub fn undo_file(&self, from_or_to: &Path) -> Result<(), OrganizerError> {
+        let _lock = self.lock_run()?;
+        let wanted =
+            std::path::absolute(from_or_to).map_err(|e| OrganizerError::io_at(from_or_to, e))?;
+        let same = |recorded: &Path| std::path::absolute(recorded).is_ok_and(|p| p == wanted);
+        match self
+            .history
+            .take_latest_matching(|m| same(&m.from) || same(&m.to))?
+        {
+            Some(mov) => self.revert_all(vec![mov]),
+            None => {
+                warn!("No recorded move of {:?}", from_or_to);
+                Ok(())
             }
         }
-        Ok(())
     }
+
+    /// Repeats the move most recently reverted by an undo command
+    pub fn redo_last(&self) -> Result<(), OrganizerError> {
+        let _lock = self.lock_run()?;
+        let Some(mov) = self.history.pop_redo()? else {
+            warn!("Nothing to redo");
+            return Ok(());
+        };
+        if !mov.from.exists() {
+            warn!("Source file missing: {:?}", mov.from);
+            return Ok(());
+        }
+        match self.redo(&mov) {
+            Ok(redone) => {
+                self.history.push(redone)?;
+                self.history.flush()
+            }
+            Err(e) => {
+                // keep it for another attempt
+                self.history.push_redo(vec![mov])?;
+                Err(e)
+            }
+        }
+    }
+
+    fn redo(&self, mov: &MovedFile) -> Result<MovedFile, OrganizerError> {
+        let mut target = mov.to.clone();
+        if target.exists() {
+            target = resolve_conflict(&mov.to, self.conflict_template(), |p| p.exists())?;
+            warn!("{:?} was taken by another file, using {:?}", mov.to, target);
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent).map_err(|e| OrganizerError::io_at(parent, e))?;
+        }
+        info!("Redo: {:?} -> {:?}", mov.from, target);
+        match mov.kind {
+            TransferKind::Move => move_file(&mov.from, &target, self.copy_options(), None)?,
+            TransferKind::Copy => copy_file(&mov.from, &target, self.copy_options(), None)?,
+            TransferKind::HardLink | TransferKind::Symlink => {
+                link_file(&mov.from, &target, mov.kind)?
+            }
+        }
+        // never overwrites, so nothing goes to the trash
+        Ok(MovedFile {
+            to: target,
+            time: Utc::now(),
+            replaced_trashed: false,
+            ..mov.clone()
+        })
+    }
+
+    /// Reverts moves newest-first; the reverted ones can be redone with `redo_last`.
+    /// A move that fails to revert goes back into the history and the rest
+    /// are still tried; one failure is returned as it is, more as `UndoIncomplete`.
+    fn revert_all(&self, mut moves: Vec<MovedFile>) -> Result<(), OrganizerError> {
+        moves.sort_by_key(|m| m.time);
+        let mut undone = Vec::new();
+        let mut kept = Vec::new();
+        let mut errors = Vec::new();
+        for mov in moves.into_iter().rev() {
+            match revert(&mov, self.conflict_template()) {
+                Ok(redo) => undone.extend(redo),
+                Err(e) => {
+                    error!("Could not undo {:?} -> {:?}: {}", mov.from, mov.to, e);
+                    errors.push((mov.to.clone(), e));
+                    kept.push(mov);
+                }
+            }
+        }
+        self.history.push_redo(undone)?;
+        self.history.restore(kept)?;
+        match errors.len() {
+            0 => Ok(()),
+            1 => Err(errors.remove(0).1),
+            _ => Err(OrganizerError::UndoIncomplete(
+                errors.into_iter().map(|(path, _)| path).collect(),
+            )),
+        }
+    }
+}
+
+/// Recreates folders deleted by the empty-folder cleanup
+fn restore_dirs(dirs: Vec<RemovedDir>) {
+    for dir in dirs {
+        match fs::create_dir_all(&dir.path) {
+            Ok(()) => info!("Undo: recreate folder {:?}", dir.path),
+            Err(e) => warn!("Could not recreate folder {:?}: {}", dir.path, e),
+        }
+    }
+}
+
+/// Puts one file back; returns the move that would redo it (the original
+/// name may have been taken meanwhile), or `None` if there was nothing to revert
+fn revert(mov: &MovedFile, conflict_template: &str) -> Result<Option<MovedFile>, OrganizerError> {
+    // a symlink whose original is gone still counts
+    if fs::symlink_metadata(&mov.to).is_err() {
+        warn!("Destination file missing: {:?}", mov.to);
+        return Ok(None);
+    }
+    if mov.replaced_trashed {
+        warn!(
+            "{:?} had replaced a file that was sent to the trash; restore that one from the trash",
+            mov.to
+        );
+    }
+    match mov.kind {
+        TransferKind::Move => {
+            // the original folder may be gone, e.g. after --cleanup
+            if let Some(parent) = mov.from.parent() {
+                fs::create_dir_all(parent).map_err(|e| OrganizerError::io_at(parent, e))?;
+            }
+            let mut restore_to = mov.from.clone();
+            if restore_to.exists() {
+                restore_to = resolve_conflict(&mov.from, conflict_template, |p| p.exists())?;
+                warn!(
+                    "{:?} was taken by another file, restoring as {:?}",
+                    mov.from, restore_to
+                );
+            }
+            info!("Undo: {:?} -> {:?}", mov.to, restore_to);
+            move_file(
+                &mov.to,
+                &restore_to,
+                CopyOptions {
+                    preserve_times: true,
+                    verify: true,
+                    use_trash: false,
+                },
+                None,
+            )?;
+            Ok(Some(MovedFile {
+                from: restore_to,
+                ..mov.clone()
+            }))
+        }
+        TransferKind::Copy => {
+            info!("Undo: remove copy {:?}", mov.to);
+            fs::remove_file(&mov.to).map_err(|e| OrganizerError::io_at(&mov.to, e))?;
+            Ok(Some(mov.clone()))
+        }
+        TransferKind::HardLink if !mov.from.exists() => {
+            // the link is the last name of the file now; put it back instead
+            if let Some(parent) = mov.from.parent() {
+                fs::create_dir_all(parent).map_err(|e| OrganizerError::io_at(parent, e))?;
+            }
+            info!(
+                "Undo: {:?} -> {:?} (original was removed)",
+                mov.to, mov.from
+            );
+            fs::rename(&mov.to, &mov.from).map_err(|e| OrganizerError::io_at(&mov.from, e))?;
+            Ok(Some(mov.clone()))
+        }
+        TransferKind::HardLink | TransferKind::Symlink => {
+            info!("Undo: remove link {:?}", mov.to);
+            fs::remove_file(&mov.to).map_err(|e| OrganizerError::io_at(&mov.to, e))?;
+            Ok(Some(mov.clone()))
+        }
+    }
+}
+
+fn platform_case_insensitive() -> bool {
+    cfg!(any(windows, target_os = "macos"))
+}
+
+/// Creates a lowercase probe file in `dir` (or its nearest existing ancestor)
+/// and checks whether its uppercase name resolves to it. Falls back to the
+/// platform default if the directory is not writable.
+fn probe_case_insensitive(dir: &Path) -> bool {
+    let platform_default = platform_case_insensitive();
+    let Some(existing) = dir.ancestors().find(|p| p.is_dir()) else {
+        return platform_default;
+    };
+    let name = format!(".smart_organizer_case_probe_{}", std::process::id());
+    let probe = existing.join(&name);
+    if fs::File::create(&probe).is_err() {
+        return platform_default;
+    }
+    let insensitive = existing.join(name.to_uppercase()).exists();
+    if let Err(e) = fs::remove_file(&probe) {
+        warn!("Could not remove case probe {:?}: {}", probe, e);
+    }
+    insensitive
+}
+
+/// Turns a category from the rule engine into a relative path that stays
+/// inside `dst_dir`: `.` components are dropped, while `..`, absolute paths
+/// and drive prefixes are rejected so a rules file cannot write elsewhere.
+pub fn normalize_target(target: &str) -> Result<PathBuf, OrganizerError> {
+    let mut normalized = PathBuf::new();
+    for component in Path::new(target).components() {
+        match component {
+            Component::Normal(part) => normalized.push(part),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => {
+                return Err(OrganizerError::UnsafeTarget(target.to_string()));
+            }
+        }
+    }
+    Ok(normalized)
+}
+
+/// Checks a `conflict_template`: it needs `{n}` to number the candidates,
+/// and must make a plain file name
+pub fn validate_conflict_template(template: &str) -> Result<(), String> {
+    if !template.contains("{n}") {
+        return Err(format!("conflict template {:?} has no {{n}}", template));
+    }
+    if template.contains(['/', '\\']) {
+        return Err(format!(
+            "conflict template {:?} contains a path separator",
+            template
+        ));
+    }
+    Ok(())
+}
+
+/// `target` renamed per `template`; for a name without an extension
+/// `.{ext}` is left out
+fn conflict_candidate(target: &Path, template: &str, n: usize) -> PathBuf {
+    let stem = target
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("file");
+    let name = match target.extension().and_then(|s| s.to_str()) {
+        Some(ext) => template.replace("{ext}", ext),
+        None => template.replace(".{ext}", "").replace("{ext}", ""),
+    };
+    target.with_file_name(name.replace("{stem}", stem).replace("{n}", &n.to_string()))
+}
+
+/// First candidate from `template` (n = 1, 2, …) next to `target` that is not `taken`
+fn resolve_conflict(
+    target: &Path,
+    template: &str,
+    taken: impl Fn(&Path) -> bool,
+) -> Result<PathBuf, OrganizerError> {
+    validate_conflict_template(template).map_err(OrganizerError::Other)?;
+    for i in 1.. {
+        let candidate = conflict_candidate(target, template, i);
+        if !taken(&candidate) {
+            debug!("Conflict on {:?} resolved as {:?}", target, candidate);
+            return Ok(candidate);
+        }
+        debug!("Conflict candidate {:?} is taken", candidate);
+    }
+    Err(OrganizerError::Other(
+        "Unable to resolve name conflict".into(),
+    ))
+}
+
+/// The outermost non-folder among `target_dir` and its ancestors up to
+/// `root`, which would keep `create_dir_all(target_dir)` from working
+fn file_in_the_way(root: &Path, target_dir: &Path) -> Option<PathBuf> {
+    target_dir
+        .ancestors()
+        .take_while(|dir| dir.starts_with(root))
+        .filter(|dir| fs::metadata(dir).is_ok_and(|meta| !meta.is_dir()))
+        .last()
+        .map(Path::to_path_buf)
+}
+
+/// Total size of the files below `dir`, for the report and `max_rate`
+fn dir_size(dir: &Path) -> u64 {
+    WalkDir::new(dir)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| e.metadata().ok())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+/// Folder of `bucket_by_initial` for `file_name`: its first letter or digit
+/// in upper case (`report.pdf` -> `R`), `#` for anything else
+fn initial_bucket(file_name: &OsStr) -> String {
+    match file_name.to_string_lossy().chars().next() {
+        Some(c) if c.is_alphanumeric() => c.to_uppercase().collect(),
+        _ => "#".to_string(),
+    }
+}
+
+/// `dir/stem.ext` -> `dir/stem_<suffix>.ext`
+fn with_suffix(target: &Path, suffix: &str) -> PathBuf {
+    let stem = target
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or("file");
+    match target.extension().and_then(|s| s.to_str()) {
+        Some(ext) => target.with_file_name(format!("{}_{}.{}", stem, suffix, ext)),
+        None => target.with_file_name(format!("{}_{}", stem, suffix)),
+    }
+}
+
+fn same_content(a: &Path, b: &Path) -> io::Result<bool> {
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    Ok(file_hash(a)? == file_hash(b)?)
+}
+
+/// Dotfiles everywhere, plus entries with the hidden or system attribute on Windows
+fn is_hidden(path: &Path) -> bool {
+    let dotfile = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .is_some_and(|n| n.starts_with('.'));
+    dotfile || has_hidden_attribute(path)
 }
 
-fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+#[cfg(windows)]
+fn has_hidden_attribute(path: &Path) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
+    fs::symlink_metadata(path)
+        .map(|m| m.file_attributes() & (FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM) != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(windows))]
+fn has_hidden_attribute(_path: &Path) -> bool {
+    false
+}
+
+/// How `copy_file` treats the copy once the data is written
+#[derive(Debug, Clone, Copy)]
+struct CopyOptions {
+    /// Carry over the modification/access times and permissions
+    preserve_times: bool,
+    /// Fail with `CopyMismatch` unless the copy has the same content
+    verify: bool,
+    /// Trash the source of a cross-device move instead of deleting it
+    use_trash: bool,
+}
+
+/// Lets `copy_file` be cancelled between chunks and report how many bytes
+/// it has written
+struct CopyWatch<'a> {
+    cancel: &'a AtomicBool,
+    on_chunk: &'a dyn Fn(u64),
+}
+
+fn move_file(
+    from: &Path,
+    to: &Path,
+    options: CopyOptions,
+    watch: Option<&CopyWatch>,
+) -> Result<(), OrganizerError> {
     match fs::rename(from, to) {
         Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices && from.is_dir() => {
+            Err(OrganizerError::Other(format!(
+                "Cannot move folder {:?} to another filesystem",
+                from
+            )))
+        }
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            // a cancelled copy fails here, before the source is touched
+            copy_file(from, to, options, watch)?;
+            if options.use_trash {
+                return move_to_trash(from);
+            }
+            fs::remove_file(from).map_err(|e| OrganizerError::io_at(from, e))
+        }
+        Err(e) => Err(rename_error(from, to, e)),
+    }
+}
+
+/// Blames a failed rename on the side that caused it: a source that is gone
+/// or whose folder isn't writable names `from`, everything else `to`
+fn rename_error(from: &Path, to: &Path, e: io::Error) -> OrganizerError {
+    let source_side = match e.kind() {
+        io::ErrorKind::NotFound => fs::symlink_metadata(from).is_err(),
+        io::ErrorKind::PermissionDenied => {
+            let writable = |path: &Path| path.parent().is_some_and(dir_writable);
+            // both writable: the file itself is protected (sticky folder, immutable flag)
+            !writable(from) || writable(to)
+        }
+        _ => false,
+    };
+    OrganizerError::io_at(if source_side { from } else { to }, e)
+}
+
+/// Whether a file can be created in `dir`, tried with a probe file
+fn dir_writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(
+        ".smart_organizer_write_probe_{}",
+        std::process::id()
+    ));
+    match fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)
+    {
+        Ok(_) => fs::remove_file(&probe).is_ok(),
+        Err(e) => e.kind() == io::ErrorKind::AlreadyExists,
+    }
+}
+
+/// Creates a hard or symbolic link to `from` at `to`. A file already there
+/// (the plan decided to overwrite it) is only replaced once the link exists.
+fn link_file(from: &Path, to: &Path, kind: TransferKind) -> Result<(), OrganizerError> {
+    let replace = fs::symlink_metadata(to).is_ok();
+    let link = if replace {
+        let mut name = OsString::from(".");
+        name.push(to.file_name().unwrap_or_default());
+        name.push(".smart_organizer_link");
+        to.with_file_name(name)
+    } else {
+        to.to_path_buf()
+    };
+    let result = match kind {
+        TransferKind::Symlink => {
+            // a relative source would resolve against the link's folder
+            let original = std::path::absolute(from).map_err(|e| OrganizerError::io_at(from, e))?;
+            symlink_file(&original, &link)
+        }
+        _ => fs::hard_link(from, &link),
+    };
+    match result {
+        Ok(()) => {}
         Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
-            fs::copy(from, to)?;
-            fs::remove_file(from)?;
+            return Err(OrganizerError::CrossDeviceLink {
+                from: from.to_path_buf(),
+                to: to.to_path_buf(),
+            })
+        }
+        Err(e) => return Err(OrganizerError::io_at(to, e)),
+    }
+    if replace {
+        fs::rename(&link, to).map_err(|e| {
+            let _ = fs::remove_file(&link);
+            OrganizerError::io_at(to, e)
+        })?;
+    }
+    Ok(())
+}
+
+/// Whether `link` already is a link to `original`, left by an earlier run
+/// with `link_mode`
+fn links_to(link: &Path, original: &Path) -> bool {
+    if let Ok(target) = fs::read_link(link) {
+        return std::path::absolute(original).is_ok_and(|original| original == target);
+    }
+    same_inode(link, original)
+}
+
+/// Whether a rename from `from` to `to` stays on one filesystem; assumed
+/// where it can't be told, so that only `move_file` finds out
+#[cfg(unix)]
+fn same_filesystem(from: &Path, to: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    let target = to.ancestors().skip(1).find_map(|d| fs::metadata(d).ok());
+    match (fs::metadata(from), target) {
+        (Ok(from), Some(to)) => from.dev() == to.dev(),
+        _ => true,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_filesystem(_from: &Path, _to: &Path) -> bool {
+    true
+}
+
+#[cfg(unix)]
+fn same_inode(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_inode(_a: &Path, _b: &Path) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn symlink_file(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink_file(original: &Path, link: &Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
+
+fn move_to_trash(path: &Path) -> Result<(), OrganizerError> {
+    trash::delete(path).map_err(|e| OrganizerError::Trash {
+        path: path.to_path_buf(),
+        reason: e.to_string(),
+    })
+}
+
+/// Failing to carry over timestamps/permissions only logs a warning;
+/// a copy that fails verification is left in place next to its source.
+/// Cancelling through `watch` removes the partial copy.
+fn copy_file(
+    from: &Path,
+    to: &Path,
+    options: CopyOptions,
+    watch: Option<&CopyWatch>,
+) -> Result<(), OrganizerError> {
+    let existed = to.exists();
+    match stream_copy(from, to, watch) {
+        Ok(true) => {}
+        Ok(false) => {
+            info!("Copy of {:?} cancelled, removing {:?}", from, to);
+            let _ = fs::remove_file(to);
+            return Err(OrganizerError::CopyCancelled(from.to_path_buf()));
+        }
+        Err(e) => {
+            // don't leave a truncated copy behind (e.g. destination full), but
+            // never delete a file we were refused to overwrite
+            if !existed {
+                let _ = fs::remove_file(to);
+            }
+            return Err(OrganizerError::io_at(to, e));
+        }
+    }
+    if options.verify {
+        let same = same_content(from, to).map_err(|e| OrganizerError::io_at(to, e))?;
+        if !same {
+            return Err(OrganizerError::CopyMismatch {
+                from: from.to_path_buf(),
+                to: to.to_path_buf(),
+            });
+        }
+    }
+    if options.preserve_times {
+        if let Err(e) = copy_metadata(from, to) {
+            warn!(
+                "Cannot keep timestamps/permissions of {:?} on {:?}: {}",
+                from, to, e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Fails with `InsufficientSpace` unless `from` fits into the free space of
+/// the filesystem `to` goes to; if that can't be told, the copy is attempted
+fn check_space(from: &Path, to: &Path) -> Result<(), OrganizerError> {
+    // the target folder may not exist yet
+    let Some(dir) = to.ancestors().skip(1).find(|d| d.is_dir()) else {
+        return Ok(());
+    };
+    let needed = fs::metadata(from)
+        .map_err(|e| OrganizerError::io_at(from, e))?
+        .len();
+    match fs2::available_space(dir) {
+        Ok(available) if available < needed => Err(OrganizerError::InsufficientSpace {
+            path: from.to_path_buf(),
+            needed,
+            available,
+        }),
+        Ok(_) => Ok(()),
+        Err(e) => {
+            debug!("Cannot tell the free space of {:?}: {}", dir, e);
             Ok(())
         }
-        Err(e) => Err(e),
+    }
+}
+
+/// Copies the contents and permissions of `from` in `COPY_CHUNK` pieces, as
+/// `fs::copy` would; `Ok(false)` if `watch` was cancelled before the end
+fn stream_copy(from: &Path, to: &Path, watch: Option<&CopyWatch>) -> io::Result<bool> {
+    use std::io::{Read, Write};
+    let mut reader = fs::File::open(from)?;
+    let permissions = reader.metadata()?.permissions();
+    let mut writer = fs::File::create(to)?;
+    let mut buf = vec![0; COPY_CHUNK];
+    let mut copied = 0;
+    loop {
+        if watch.is_some_and(|watch| watch.cancel.load(Ordering::Relaxed)) {
+            return Ok(false);
+        }
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        copied += n as u64;
+        if let Some(watch) = watch {
+            (watch.on_chunk)(copied);
+        }
+    }
+    fs::set_permissions(to, permissions)?;
+    Ok(true)
+}
+
+fn copy_metadata(from: &Path, to: &Path) -> io::Result<()> {
+    let meta = fs::metadata(from)?;
+    filetime::set_file_times(
+        to,
+        FileTime::from_last_access_time(&meta),
+        FileTime::from_last_modification_time(&meta),
+    )?;
+    // last: a read-only file would refuse the timestamp update on Windows
+    fs::set_permissions(to, meta.permissions())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rules::ExtensionRuleEngine;
+
+    fn organizer(src: &Path, dst: &Path, history: &Path) -> Organizer<ExtensionRuleEngine> {
+        let config = OrganizerConfig {
+            src_dir: src.to_path_buf(),
+            dst_dir: dst.to_path_buf(),
+            case_insensitive: Some(false),
+            min_age: Some(Duration::ZERO),
+            threads: Some(1),
+            transactional: true,
+            ..Default::default()
+        };
+        Organizer::new(
+            config,
+            ExtensionRuleEngine::default(),
+            HistoryManager::new(history.to_path_buf()),
+        )
+    }
+
+    /// Records a move of `src/name` to `dst/txt/<file name>` as if it had happened
+    fn recorded_move(src: &Path, dst: &Path, name: &str, secs_ago: i64) -> MovedFile {
+        let from = src.join(name);
+        let to = dst.join("txt").join(from.file_name().unwrap());
+        fs::create_dir_all(to.parent().unwrap()).unwrap();
+        fs::write(&to, name).unwrap();
+        MovedFile {
+            from,
+            to,
+            time: Utc::now() - chrono::Duration::seconds(secs_ago),
+            session_id: 1,
+            kind: TransferKind::Move,
+            replaced_trashed: false,
+            rule_matched: None,
+        }
+    }
+
+    #[test]
+    fn undo_all_keeps_moves_that_fail_to_revert() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        let history_path = dir.path().join("history.json");
+        let history = HistoryManager::new(history_path.clone());
+        for (name, age) in [("a.txt", 3), ("blocked/b.txt", 2), ("c.txt", 1)] {
+            history.push(recorded_move(&src, &dst, name, age)).unwrap();
+        }
+        drop(history);
+        // a file where b.txt's folder has to be recreated
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("blocked"), "").unwrap();
+
+        let organizer = organizer(&src, &dst, &history_path);
+        assert!(organizer.undo_all().is_err());
+
+        assert_eq!(fs::read_to_string(src.join("a.txt")).unwrap(), "a.txt");
+        assert_eq!(fs::read_to_string(src.join("c.txt")).unwrap(), "c.txt");
+        assert!(dst.join("txt/b.txt").exists());
+        let history = organizer.history.load().unwrap();
+        assert_eq!(history.moves.len(), 1);
+        assert_eq!(history.moves[0].from, src.join("blocked/b.txt"));
+        assert_eq!(history.redo.len(), 2);
+    }
+
+    #[test]
+    fn undo_all_names_every_file_it_could_not_revert() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        let history_path = dir.path().join("history.json");
+        let history = HistoryManager::new(history_path.clone());
+        for (name, age) in [("x/a.txt", 2), ("x/b.txt", 1)] {
+            history.push(recorded_move(&src, &dst, name, age)).unwrap();
+        }
+        drop(history);
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("x"), "").unwrap();
+
+        let organizer = organizer(&src, &dst, &history_path);
+        match organizer.undo_all() {
+            Err(OrganizerError::UndoIncomplete(paths)) => assert_eq!(paths.len(), 2),
+            other => panic!("expected UndoIncomplete, got {:?}", other),
+        }
+        assert_eq!(organizer.history.load().unwrap().moves.len(), 2);
+    }
+
+    #[test]
+    fn file_in_the_way_fails_one_category_without_a_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("a.pdf"), "a").unwrap();
+        fs::write(src.join("b.txt"), "b").unwrap();
+        let organizer = organizer(&src, &dst, &dir.path().join("history.json"));
+        let plan = organizer.plan().unwrap();
+        assert_eq!(plan.len(), 2);
+        // only there after planning, so b.txt fails when it is executed
+        fs::create_dir_all(&dst).unwrap();
+        fs::write(dst.join("txt"), "").unwrap();
+
+        let report = organizer.execute_plan(plan).unwrap();
+        assert_eq!(report.files_moved, 1);
+        assert_eq!(report.failed, 1);
+        assert!(matches!(
+            organizer.errors()[0].1,
+            OrganizerError::FileInTheWay(_)
+        ));
+        assert_eq!(fs::read_to_string(dst.join("pdf/a.pdf")).unwrap(), "a");
+        assert!(src.join("b.txt").exists());
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn transactional_run_rolls_back_on_a_fatal_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        fs::create_dir_all(&src).unwrap();
+        fs::create_dir_all(&dst).unwrap();
+        fs::write(src.join("a.pdf"), "a").unwrap();
+        fs::write(src.join("b.txt"), "b").unwrap();
+        fs::write(dst.join("keep.txt"), "keep").unwrap();
+        let mut organizer = organizer(&src, &dst, &dir.path().join("history.json"));
+        organizer.config.simulate_errors = vec![("b.txt".into(), SimulatedFailure::StorageFull)];
+        // one thread in plan order: a.pdf is moved before b.txt fails
+        let mut plan = organizer.plan().unwrap();
+        plan.sort_by(|a, b| a.from.cmp(&b.from));
+
+        match organizer.execute_plan(plan) {
+            Err(e) => assert!(e.is_fatal()),
+            Ok(report) => panic!("expected a fatal error, got {:?}", report),
+        }
+        assert_eq!(fs::read_to_string(src.join("a.pdf")).unwrap(), "a");
+        assert_eq!(fs::read_to_string(src.join("b.txt")).unwrap(), "b");
+        let left: Vec<_> = fs::read_dir(&dst)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(left, ["keep.txt"]);
+        assert!(organizer.history.load().unwrap().moves.is_empty());
+    }
+
+    #[test]
+    fn copy_that_does_not_fit_is_skipped_without_a_target_folder() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        fs::create_dir_all(&src).unwrap();
+        fs::write(src.join("a.txt"), "a").unwrap();
+        // sparse, so it takes no space itself
+        let free = fs2::available_space(dir.path()).unwrap();
+        fs::File::create(src.join("big.bin"))
+            .unwrap()
+            .set_len(free + (1 << 30))
+            .unwrap();
+        let mut organizer = organizer(&src, &dst, &dir.path().join("history.json"));
+        organizer.config.copy_only = true;
+
+        let report = organizer.organize().unwrap();
+        assert_eq!(report.files_moved, 1);
+        assert_eq!(report.no_space, 1);
+        assert!(organizer.errors().is_empty());
+        assert!(dst.join("txt/a.txt").exists());
+        assert!(!dst.join("bin").exists());
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn simulated_failures_are_listed_in_the_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        let (src, dst) = (dir.path().join("src"), dir.path().join("dst"));
+        fs::create_dir_all(&src).unwrap();
+        for name in ["a.txt", "b1.txt", "b2.txt", "c.pdf"] {
+            fs::write(src.join(name), name).unwrap();
+        }
+        let mut organizer = organizer(&src, &dst, &dir.path().join("history.json"));
+        organizer.config.simulate_errors = vec![
+            ("b1.txt".into(), SimulatedFailure::Other),
+            // a source without permission is a per-file failure, even when transactional
+            ("b2.txt".into(), SimulatedFailure::PermissionDenied),
+            ("*.pdf".into(), SimulatedFailure::Other),
+        ];
+
+        let report = organizer.organize().unwrap();
+        assert_eq!(report.files_moved, 1);
+        assert_eq!(report.failed, 3);
+        let mut failed: Vec<_> = organizer
+            .errors()
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect();
+        failed.sort();
+        assert_eq!(
+            failed,
+            ["b1.txt", "b2.txt", "c.pdf"].map(|name| src.join(name))
+        );
+        assert!(dst.join("txt/a.txt").exists());
+        // failed before its target folder was made
+        assert!(!dst.join("pdf").exists());
     }
 }
@@ -0,0 +1,106 @@
+//! Checks and repairs single file/folder names that the destination OS would
+//! refuse (`a:b` on Windows, names longer than 255 bytes)
+
+/// Longest name accepted everywhere: 255 bytes on Unix filesystems,
+/// 255 UTF-16 units on Windows (never more than the UTF-8 byte count)
+const MAX_NAME_BYTES: usize = 255;
+
+/// Device names Windows reserves in every folder, with or without an extension
+const WINDOWS_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Platform {
+    Windows,
+    Unix,
+}
+
+impl Platform {
+    pub fn current() -> Self {
+        if cfg!(windows) {
+            Platform::Windows
+        } else {
+            Platform::Unix
+        }
+    }
+
+    fn is_illegal(self, c: char) -> bool {
+        match self {
+            Platform::Windows => {
+                c.is_control() || matches!(c, '<' | '>' | ':' | '"' | '/' | '\\' | '|' | '?' | '*')
+            }
+            Platform::Unix => matches!(c, '\0' | '/'),
+        }
+    }
+}
+
+/// Why `name` can't be used on `platform`, or `None` if it can
+pub fn invalid_reason(name: &str, platform: Platform) -> Option<String> {
+    if let Some(c) = name.chars().find(|&c| platform.is_illegal(c)) {
+        return Some(format!("contains {:?}", c));
+    }
+    if platform == Platform::Windows {
+        if name.ends_with(['.', ' ']) {
+            return Some("ends with a dot or space".into());
+        }
+        if is_reserved(name) {
+            return Some("is a reserved device name".into());
+        }
+    }
+    if name.len() > MAX_NAME_BYTES {
+        return Some(format!(
+            "is {} bytes long (max {})",
+            name.len(),
+            MAX_NAME_BYTES
+        ));
+    }
+    None
+}
+
+/// `name` with illegal characters replaced by `_`, reserved names suffixed
+/// with `_` and overlong names shortened, keeping the extension
+pub fn sanitize(name: &str, platform: Platform) -> String {
+    let mut clean: String = name
+        .chars()
+        .map(|c| if platform.is_illegal(c) { '_' } else { c })
+        .collect();
+    if platform == Platform::Windows {
+        clean.truncate(clean.trim_end_matches(['.', ' ']).len());
+        if clean.is_empty() {
+            clean.push('_');
+        }
+        if is_reserved(&clean) {
+            clean = match clean.split_once('.') {
+                Some((stem, ext)) => format!("{}_.{}", stem, ext),
+                None => format!("{}_", clean),
+            };
+        }
+    }
+    truncate(&clean)
+}
+
+fn is_reserved(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    WINDOWS_RESERVED
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+}
+
+/// Cuts the stem so that the name fits `MAX_NAME_BYTES`; an extension that
+/// alone would not fit is cut along with the rest
+fn truncate(name: &str) -> String {
+    if name.len() <= MAX_NAME_BYTES {
+        return name.to_string();
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(dot) if dot > 0 && name.len() - dot < MAX_NAME_BYTES / 2 => name.split_at(dot),
+        _ => (name, ""),
+    };
+    let mut end = MAX_NAME_BYTES - ext.len();
+    while !stem.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}{}", &stem[..end], ext)
+}
@@ -0,0 +1,74 @@
+//! Named rule sets stored as `<config dir>/smart-organizer/<name>.json`
+use crate::{errors::OrganizerError, rules::RulesConfig};
+use directories::ProjectDirs;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Folder holding the profiles (`~/.config/smart-organizer` on Linux)
+pub fn profiles_dir() -> Result<PathBuf, OrganizerError> {
+    ProjectDirs::from("", "", "smart-organizer")
+        .map(|dirs| dirs.config_dir().to_path_buf())
+        .ok_or_else(|| OrganizerError::Other("Cannot determine the config folder".into()))
+}
+
+/// Rules file of the profile `name`; fails if there is no such profile
+pub fn profile_path(name: &str) -> Result<PathBuf, OrganizerError> {
+    check_name(name)?;
+    let path = profiles_dir()?.join(format!("{}.json", name));
+    if !path.is_file() {
+        return Err(OrganizerError::Other(format!(
+            "No rules profile named {:?} (see `rules list`)",
+            name
+        )));
+    }
+    Ok(path)
+}
+
+/// Validates the rules in `file` and stores them as profile `name`,
+/// replacing an existing profile of that name
+pub fn save_profile(name: &str, file: &Path) -> Result<(PathBuf, RulesConfig), OrganizerError> {
+    check_name(name)?;
+    let text = fs::read_to_string(file).map_err(|e| OrganizerError::io_at(file, e))?;
+    let config = RulesConfig::from_json(&text)?;
+    let dir = profiles_dir()?;
+    fs::create_dir_all(&dir).map_err(|e| OrganizerError::io_at(&dir, e))?;
+    let path = dir.join(format!("{}.json", name));
+    fs::write(&path, text).map_err(|e| OrganizerError::io_at(&path, e))?;
+    Ok((path, config))
+}
+
+/// Names of the saved profiles, sorted
+pub fn list_profiles() -> Result<Vec<String>, OrganizerError> {
+    let dir = profiles_dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(OrganizerError::io_at(&dir, e)),
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .filter_map(|path| path.file_stem()?.to_str().map(str::to_string))
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Profile names become file names, so keep them to one plain path component
+fn check_name(name: &str) -> Result<(), OrganizerError> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(OrganizerError::Other(format!(
+            "Invalid profile name {:?}: use letters, digits, '-', '_' and '.'",
+            name
+        )));
+    }
+    Ok(())
+}
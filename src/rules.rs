@@ -1,24 +1,249 @@
+use crate::errors::OrganizerError;
+use chrono::{
+    format::{Item, StrftimeItems},
+    DateTime, Local, NaiveDate, NaiveDateTime,
+};
+use globset::{GlobBuilder, GlobMatcher};
+use infer::MatcherType;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
-use std::path::Path;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt, fs,
+    io::Read,
+    path::{Component, Path, PathBuf},
+    sync::OnceLock,
+    time::{Duration, SystemTime},
+};
 
 /// Основной интерфейс классификатора
 pub trait RuleEngine: Send + Sync {
-    fn classify(&self, file: &Path) -> String;
+    /// Имя подпапки для файла. Ошибка (файл не удалось прочитать и т.п.)
+    /// попадает в список ошибок прогона, а файл остаётся на месте.
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError>;
+
+    /// Как `classify`, но `None` вместо собственной fallback‑папки —
+    /// «нет мнения», чтобы `ChainRuleEngine` мог спросить следующий движок.
+    /// По умолчанию мнение есть всегда.
+    fn try_classify(&self, file: &Path, meta: &FileMeta) -> Result<Option<String>, OrganizerError> {
+        self.classify(file, meta).map(Some)
+    }
+
+    /// Как `classify`, но правило может указать свой корень вместо `dst_dir`,
+    /// и известно, что выбрало папку. По умолчанию корня нет — файл идёт в
+    /// `dst_dir`; без мнения (`try_classify_target`) это "fallback".
+    fn classify_target(
+        &self,
+        file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Classification, OrganizerError> {
+        match self.try_classify_target(file, meta)? {
+            Some(target) => Ok(target),
+            None => Ok(Classification::fallback(self.classify(file, meta)?)),
+        }
+    }
+
+    /// Как `try_classify`, но с корнем (см. `classify_target`); правилом
+    /// считается имя движка
+    fn try_classify_target(
+        &self,
+        file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Option<Classification>, OrganizerError> {
+        Ok(self.try_classify(file, meta)?.map(|subdir| Classification {
+            root: None,
+            subdir,
+            rule: Some(self.name().to_string()),
+        }))
+    }
+
+    /// Имя движка для истории, как в поле "engine" rules.json; для чужих
+    /// движков — имя типа
+    fn name(&self) -> &str {
+        std::any::type_name::<Self>()
+    }
+}
+
+/// Куда положить файл: подпапка `subdir` внутри `root`, а без `root` — внутри `dst_dir`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Classification {
+    /// Абсолютный корень назначения из правила (`target_root`)
+    pub root: Option<PathBuf>,
+    pub subdir: String,
+    /// Что выбрало папку: шаблон правила, имя движка или `FALLBACK_RULE`
+    pub rule: Option<String>,
+}
+
+/// `Classification::rule` файла, для которого ни у одного правила не нашлось мнения
+pub const FALLBACK_RULE: &str = "fallback";
+
+impl Classification {
+    pub fn fallback(subdir: String) -> Self {
+        Self {
+            root: None,
+            subdir,
+            rule: Some(FALLBACK_RULE.to_string()),
+        }
+    }
+}
+
+impl From<String> for Classification {
+    fn from(subdir: String) -> Self {
+        Self {
+            root: None,
+            subdir,
+            rule: None,
+        }
+    }
+}
+
+/// Сведения о файле для движков. Размер и время изменения читаются один раз
+/// перед классификацией, MIME‑тип определяется по содержимому при первом запросе.
+pub struct FileMeta {
+    path: PathBuf,
+    /// Исходная папка, в которой лежит файл, если известна
+    source: Option<PathBuf>,
+    pub size: u64,
+    /// `None`, если файловая система не хранит время изменения
+    pub modified: Option<SystemTime>,
+    kind: OnceLock<Option<infer::Type>>,
+}
+
+impl FileMeta {
+    pub fn from_path(path: &Path) -> Result<Self, OrganizerError> {
+        let meta = fs::metadata(path).map_err(|e| OrganizerError::io_at(path, e))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            source: None,
+            size: meta.len(),
+            modified: meta.modified().ok(),
+            // у папки (`TreatDirs::MoveTopLevelDirs`) нет содержимого для определения типа
+            kind: if meta.is_dir() {
+                OnceLock::from(None)
+            } else {
+                OnceLock::new()
+            },
+        })
+    }
+
+    /// Сведения для имени, которого может не быть на диске (`test-rule`):
+    /// размер 0, время изменения и MIME‑тип неизвестны
+    pub fn for_name(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            source: None,
+            size: 0,
+            modified: None,
+            kind: OnceLock::from(None),
+        }
+    }
+
+    /// Запоминает исходную папку файла для `relative_path`
+    pub fn with_source(mut self, src_dir: &Path) -> Self {
+        self.source = Some(src_dir.to_path_buf());
+        self
+    }
+
+    /// Путь файла внутри исходной папки (`Screenshots/a.png`); если папка
+    /// не задана — полный путь
+    pub fn relative_path(&self) -> &Path {
+        self.source
+            .as_deref()
+            .and_then(|src_dir| self.path.strip_prefix(src_dir).ok())
+            .unwrap_or(&self.path)
+    }
+
+    /// MIME‑тип по первым байтам файла ("image/jpeg"); `None`, если тип не распознан
+    pub fn mime(&self) -> Result<Option<&'static str>, OrganizerError> {
+        Ok(self.kind()?.map(|kind| kind.mime_type()))
+    }
+
+    fn kind(&self) -> Result<Option<infer::Type>, OrganizerError> {
+        if let Some(kind) = self.kind.get() {
+            return Ok(*kind);
+        }
+        let kind = sniff(&self.path).map_err(|e| OrganizerError::io_at(&self.path, e))?;
+        Ok(*self.kind.get_or_init(|| kind))
+    }
 }
 
 /* ------------------------------------------------------------------ */
 /* 1. Простейший классификатор — по расширению                         */
 /* ------------------------------------------------------------------ */
 
+/// Регистр имени папки, которое `ExtensionRuleEngine` делает из расширения
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FolderCase {
+    /// `.JPG` и `.jpg` → `jpg`
+    #[default]
+    Lower,
+    /// `.JPG` и `.jpg` → `JPG`
+    Upper,
+    /// Как в имени файла: `.JPG` → `JPG`, `.jpg` → `jpg`
+    Preserve,
+}
+
+fn default_no_ext_dir() -> String {
+    "no_extension".to_string()
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ExtensionRuleEngine;
+#[serde(deny_unknown_fields)]
+pub struct ExtensionRuleEngine {
+    /// Папка для файлов без расширения (`README`, `Makefile`)
+    #[serde(default = "default_no_ext_dir")]
+    pub no_ext_dir: String,
+    /// Регистр имени папки; только для вида — `Lower` и `Upper` собирают
+    /// `.JPG` и `.jpg` в одну папку
+    #[serde(default)]
+    pub case: FolderCase,
+}
+
+impl Default for ExtensionRuleEngine {
+    fn default() -> Self {
+        Self::with_no_ext_dir(default_no_ext_dir())
+    }
+}
+
+impl ExtensionRuleEngine {
+    pub fn with_no_ext_dir(no_ext_dir: String) -> Self {
+        Self {
+            no_ext_dir,
+            case: FolderCase::default(),
+        }
+    }
+
+    pub fn with_case(mut self, case: FolderCase) -> Self {
+        self.case = case;
+        self
+    }
+}
 
 impl RuleEngine for ExtensionRuleEngine {
-    fn classify(&self, file: &Path) -> String {
-        file.extension()
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+        Ok(self
+            .try_classify(file, meta)?
+            .unwrap_or_else(|| self.no_ext_dir.clone()))
+    }
+
+    fn try_classify(
+        &self,
+        file: &Path,
+        _meta: &FileMeta,
+    ) -> Result<Option<String>, OrganizerError> {
+        Ok(file
+            .extension()
             .and_then(|e| e.to_str())
-            .map(|e| e.to_ascii_lowercase())
-            .unwrap_or_else(|| "no_extension".to_string())
+            .map(|e| match self.case {
+                FolderCase::Lower => e.to_ascii_lowercase(),
+                FolderCase::Upper => e.to_ascii_uppercase(),
+                FolderCase::Preserve => e.to_string(),
+            }))
+    }
+
+    fn name(&self) -> &str {
+        "extension"
     }
 }
 
@@ -26,52 +251,1371 @@ impl RuleEngine for ExtensionRuleEngine {
 /* 2. Расширяемые пользовательские правила (загружаются из JSON)       */
 /* ------------------------------------------------------------------ */
 
+/// Как интерпретировать `CustomRule::pattern`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchKind {
+    /// Список расширений через `|`, например "jpg|jpeg|tar.gz"
+    #[default]
+    Extension,
+    /// Регулярное выражение, проверяется по полному имени файла
+    Regex,
+    /// Glob‑шаблон (`*.pdf`, `IMG_*`), проверяется по полному имени файла
+    Glob,
+    /// Имена папок через `|` ("Screenshots|Скриншоты", регистр не важен):
+    /// правило подходит, если файл лежит в такой папке на любой глубине
+    /// внутри исходной папки
+    PathComponent,
+}
+
+/// Какое правило выбирается, если подходят несколько
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MatchMode {
+    /// Первое подходящее правило (с учётом `priority`)
+    #[default]
+    FirstMatch,
+    /// Правило с самым длинным совпадением: `tar.gz` важнее `gz`,
+    /// `IMG_*.jpg` важнее `*.jpg`. `priority` по‑прежнему учитывается первым
+    MostSpecific,
+}
+
+#[derive(Debug, Clone)]
+enum Matcher {
+    Regex(Regex),
+    Glob(GlobMatcher),
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct CustomRule {
     /// Например, "jpg|jpeg|png" → "Images"
     pub pattern: String,
+    #[serde(default)]
+    pub match_kind: MatchKind,
     pub target_dir: String,
+    /// Правила с большим приоритетом проверяются раньше; при равном
+    /// приоритете побеждает правило, объявленное выше в файле
+    #[serde(default)]
+    pub priority: i32,
+    /// Абсолютная папка, в которой создаётся `target_dir` вместо `dst_dir`
+    /// (например, архивы на другой диск)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_root: Option<PathBuf>,
+    /// Скомпилированный regex/glob, создаётся один раз при первом использовании
+    #[serde(skip)]
+    matcher: OnceLock<Option<Matcher>>,
+}
+
+impl CustomRule {
+    pub fn new(pattern: String, match_kind: MatchKind, target_dir: String, priority: i32) -> Self {
+        Self {
+            pattern,
+            match_kind,
+            target_dir,
+            priority,
+            target_root: None,
+            matcher: OnceLock::new(),
+        }
+    }
+
+    pub fn with_target_root(mut self, target_root: Option<PathBuf>) -> Self {
+        self.target_root = target_root;
+        self
+    }
+
+    /// Токены расширений в нижнем регистре, без точек и пустых
+    fn extension_tokens(&self) -> BTreeSet<String> {
+        self.pattern
+            .split('|')
+            .map(|token| token.trim().trim_start_matches('.').to_ascii_lowercase())
+            .filter(|token| !token.is_empty())
+            .collect()
+    }
+
+    /// Имена папок для `MatchKind::PathComponent` в нижнем регистре, без пустых
+    fn folder_tokens(&self) -> BTreeSet<String> {
+        self.pattern
+            .split('|')
+            .map(|token| token.trim().to_lowercase())
+            .filter(|token| !token.is_empty())
+            .collect()
+    }
+
+    /// Шаблон, которому подходит любое имя файла
+    fn is_catch_all(&self) -> bool {
+        match self.match_kind {
+            MatchKind::Extension | MatchKind::PathComponent => false,
+            MatchKind::Glob => matches!(self.pattern.trim(), "*" | "**"),
+            MatchKind::Regex => matches!(self.pattern.trim(), "" | ".*" | "^.*" | "^.*$"),
+        }
+    }
+
+    fn compile(&self) -> Result<Option<Matcher>, String> {
+        if let Some(root) = self.target_root.as_ref().filter(|root| !root.is_absolute()) {
+            return Err(format!("target_root {:?} is not an absolute path", root));
+        }
+        match self.match_kind {
+            MatchKind::Extension | MatchKind::PathComponent => Ok(None),
+            MatchKind::Regex => Regex::new(&self.pattern)
+                .map(|re| Some(Matcher::Regex(re)))
+                .map_err(|e| format!("Invalid regex {:?}: {}", self.pattern, e)),
+            MatchKind::Glob => GlobBuilder::new(&self.pattern)
+                .case_insensitive(true)
+                .build()
+                .map(|g| Some(Matcher::Glob(g.compile_matcher())))
+                .map_err(|e| format!("Invalid glob {:?}: {}", self.pattern, e)),
+        }
+    }
+
+    fn matcher(&self) -> Option<&Matcher> {
+        self.matcher
+            .get_or_init(|| self.compile().ok().flatten())
+            .as_ref()
+    }
+
+    /// Длина совпадения, если правило подходит: самый длинный совпавший
+    /// токен расширения или имени папки, длина совпадения regex или число
+    /// не‑wildcard символов glob‑шаблона. Для `PathComponent` проверяются
+    /// папки из `file`, так что путь передаётся относительно исходной папки.
+    pub fn specificity(&self, file: &Path) -> Option<usize> {
+        let name = file
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+
+        match self.match_kind {
+            MatchKind::Extension => {
+                let name = name.to_ascii_lowercase();
+                self.extension_tokens()
+                    .into_iter()
+                    .filter(|token| name.ends_with(&format!(".{}", token)))
+                    .map(|token| token.len())
+                    .max()
+            }
+            MatchKind::PathComponent => {
+                let tokens = self.folder_tokens();
+                file.parent()?
+                    .components()
+                    .filter_map(|c| match c {
+                        Component::Normal(dir) => dir.to_str(),
+                        _ => None,
+                    })
+                    .map(str::to_lowercase)
+                    .filter(|dir| tokens.contains(dir))
+                    .map(|dir| dir.len())
+                    .max()
+            }
+            MatchKind::Regex | MatchKind::Glob => match self.matcher() {
+                Some(Matcher::Regex(re)) => re.find(name).map(|m| m.len()),
+                Some(Matcher::Glob(glob)) => glob.is_match(name).then(|| {
+                    self.pattern
+                        .chars()
+                        .filter(|c| !matches!(c, '*' | '?' | '[' | ']' | '{' | '}'))
+                        .count()
+                }),
+                None => None,
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct CustomRuleEngine {
     pub rules: Vec<CustomRule>,
     pub fallback: String,
+    /// Куда класть неподошедшие файлы без расширения; `None` — в `fallback`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_extension: Option<String>,
+    #[serde(default)]
+    pub match_mode: MatchMode,
+}
+
+/// Замечание к правилу из rules.json, найденное `CustomRuleEngine::validate`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleWarning {
+    /// Индекс правила в массиве `rules` (с нуля)
+    pub index: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for RuleWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "rule #{}: {}", self.index + 1, self.reason)
+    }
+}
+
+impl CustomRuleEngine {
+    /// Компилирует шаблоны всех правил и кэширует результат,
+    /// чтобы ошибка в regex/glob не превращалась в «ничего не совпало».
+    pub fn compile(&self) -> Result<(), OrganizerError> {
+        for (i, rule) in self.rules.iter().enumerate() {
+            let matcher = rule
+                .compile()
+                .map_err(|e| OrganizerError::Other(format!("rule #{}: {}", i + 1, e)))?;
+            let _ = rule.matcher.set(matcher);
+        }
+        Ok(())
+    }
+
+    /// Ищет правила, которые скорее всего написаны с ошибкой: пустой шаблон
+    /// или папка, невалидный regex/glob, дубликаты и правила, которые никогда
+    /// не сработают, потому что их перекрывает правило с большим приоритетом
+    /// (или объявленное раньше).
+    pub fn validate(&self) -> Vec<RuleWarning> {
+        let mut warnings = Vec::new();
+        let mut warn = |index: usize, reason: String| warnings.push(RuleWarning { index, reason });
+
+        for (j, rule) in self.rules.iter().enumerate() {
+            if rule.pattern.trim().is_empty() && rule.match_kind != MatchKind::Regex {
+                warn(j, "empty pattern, never matches".into());
+                continue;
+            }
+            if rule.target_dir.trim().is_empty() {
+                warn(j, "empty target_dir".into());
+            }
+            if let Err(e) = rule.compile() {
+                warn(j, e);
+                continue;
+            }
+
+            // правила, которые выигрывают у j при совпадении
+            let winners: Vec<(usize, &CustomRule)> = self
+                .rules
+                .iter()
+                .enumerate()
+                .filter(|&(i, other)| {
+                    i != j
+                        && (other.priority > rule.priority
+                            || (other.priority == rule.priority && i < j))
+                })
+                .collect();
+
+            let duplicate = winners.iter().find(|(_, other)| {
+                other.match_kind == rule.match_kind
+                    && match rule.match_kind {
+                        MatchKind::Extension => other.extension_tokens() == rule.extension_tokens(),
+                        _ => other.pattern == rule.pattern,
+                    }
+            });
+            if let Some((i, _)) = duplicate {
+                warn(j, format!("duplicates rule #{}, never matches", i + 1));
+                continue;
+            }
+
+            if self.match_mode == MatchMode::FirstMatch {
+                if let Some((i, _)) = winners.iter().find(|(_, other)| other.is_catch_all()) {
+                    warn(j, format!("shadowed by catch-all rule #{}", i + 1));
+                    continue;
+                }
+            }
+
+            if rule.match_kind == MatchKind::Extension {
+                let claimed: BTreeSet<String> = winners
+                    .iter()
+                    .filter(|(_, other)| other.match_kind == MatchKind::Extension)
+                    .flat_map(|(_, other)| other.extension_tokens())
+                    .collect();
+                if rule.extension_tokens().is_subset(&claimed) {
+                    warn(
+                        j,
+                        "all its extensions are claimed by earlier or higher-priority rules".into(),
+                    );
+                }
+            }
+        }
+        warnings
+    }
+
+    /// Правило, которое выбирает `file` (путь внутри исходной папки)
+    pub fn matching_rule(&self, file: &Path) -> Option<&CustomRule> {
+        // max_by_key отдаёт последний из равных, поэтому индекс обёрнут в
+        // Reverse — при равенстве выигрывает правило, объявленное раньше
+        self.rules
+            .iter()
+            .enumerate()
+            .filter_map(|(i, rule)| rule.specificity(file).map(|len| (i, rule, len)))
+            .max_by_key(|&(i, rule, len)| {
+                let len = match self.match_mode {
+                    MatchMode::FirstMatch => 0,
+                    MatchMode::MostSpecific => len,
+                };
+                (rule.priority, len, std::cmp::Reverse(i))
+            })
+            .map(|(_, rule, _)| rule)
+    }
+
+    fn fallback_for(&self, file: &Path) -> String {
+        match &self.no_extension {
+            Some(dir) if file.extension().is_none() => dir.clone(),
+            _ => self.fallback.clone(),
+        }
+    }
 }
 
 impl RuleEngine for CustomRuleEngine {
-    fn classify(&self, file: &Path) -> String {
-        let ext = file
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+        Ok(self
+            .try_classify(file, meta)?
+            .unwrap_or_else(|| self.fallback_for(file)))
+    }
+
+    fn try_classify(
+        &self,
+        _file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Option<String>, OrganizerError> {
+        Ok(self
+            .matching_rule(meta.relative_path())
+            .map(|rule| rule.target_dir.clone()))
+    }
+
+    fn classify_target(
+        &self,
+        file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Classification, OrganizerError> {
+        Ok(self
+            .try_classify_target(file, meta)?
+            .unwrap_or_else(|| Classification::fallback(self.fallback_for(file))))
+    }
+
+    fn try_classify_target(
+        &self,
+        _file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Option<Classification>, OrganizerError> {
+        Ok(self
+            .matching_rule(meta.relative_path())
+            .map(|rule| Classification {
+                root: rule.target_root.clone(),
+                subdir: rule.target_dir.clone(),
+                rule: Some(rule.pattern.clone()),
+            }))
+    }
+
+    fn name(&self) -> &str {
+        "custom"
+    }
+}
+
+/* ------------------------------------------------------------------ */
+/* 3. По дате изменения файла — "YYYY/MM"                              */
+/* ------------------------------------------------------------------ */
+
+fn default_date_format() -> String {
+    "%Y/%m".to_string()
+}
+
+fn default_date_fallback() -> String {
+    "unknown_date".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DateRuleEngine {
+    /// Формат chrono, например "%Y", "%Y/%m" или "%Y/%m/%d"
+    #[serde(default = "default_date_format")]
+    pub format: String,
+    /// Куда класть файлы, у которых нельзя узнать время изменения
+    #[serde(default = "default_date_fallback")]
+    pub fallback: String,
+}
+
+impl Default for DateRuleEngine {
+    fn default() -> Self {
+        Self {
+            format: default_date_format(),
+            fallback: default_date_fallback(),
+        }
+    }
+}
+
+impl DateRuleEngine {
+    pub fn validate(&self) -> Result<(), OrganizerError> {
+        validate_date_format(&self.format)
+    }
+}
+
+/// Проверяет строку формата: chrono паникует на неизвестных спецификаторах
+fn validate_date_format(format: &str) -> Result<(), OrganizerError> {
+    if StrftimeItems::new(format).any(|item| matches!(item, Item::Error)) {
+        return Err(OrganizerError::Other(format!(
+            "Invalid date format: {:?}",
+            format
+        )));
+    }
+    Ok(())
+}
+
+impl RuleEngine for DateRuleEngine {
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+        Ok(self
+            .try_classify(file, meta)?
+            .unwrap_or_else(|| self.fallback.clone()))
+    }
+
+    fn try_classify(
+        &self,
+        _file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Option<String>, OrganizerError> {
+        Ok(meta
+            .modified
+            .map(|t| DateTime::<Local>::from(t).format(&self.format).to_string()))
+    }
+
+    fn name(&self) -> &str {
+        "date"
+    }
+}
+
+/* ------------------------------------------------------------------ */
+/* 4. По дате съёмки из EXIF — mtime меняется при копировании          */
+/* ------------------------------------------------------------------ */
+
+/// Как `DateRuleEngine`, но для фотографий берёт `DateTimeOriginal` из EXIF;
+/// файлы без EXIF и не‑изображения раскладываются по времени изменения
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExifDateRuleEngine {
+    #[serde(default = "default_date_format")]
+    pub format: String,
+    /// Куда класть файлы без даты съёмки и без времени изменения
+    #[serde(default = "default_date_fallback")]
+    pub fallback: String,
+}
+
+impl Default for ExifDateRuleEngine {
+    fn default() -> Self {
+        Self {
+            format: default_date_format(),
+            fallback: default_date_fallback(),
+        }
+    }
+}
+
+impl ExifDateRuleEngine {
+    pub fn validate(&self) -> Result<(), OrganizerError> {
+        validate_date_format(&self.format)
+    }
+}
+
+/// Дата съёмки (локальное время камеры); `None`, если EXIF нет или он битый
+fn exif_date_taken(file: &Path) -> Result<Option<NaiveDateTime>, OrganizerError> {
+    let mut reader =
+        std::io::BufReader::new(fs::File::open(file).map_err(|e| OrganizerError::io_at(file, e))?);
+    let exif = match exif::Reader::new().read_from_container(&mut reader) {
+        Ok(exif) => exif,
+        Err(exif::Error::Io(e)) => return Err(OrganizerError::io_at(file, e)),
+        Err(_) => return Ok(None),
+    };
+    let Some(field) = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY) else {
+        return Ok(None);
+    };
+    let exif::Value::Ascii(ref values) = field.value else {
+        return Ok(None);
+    };
+    let Some(dt) = values
+        .first()
+        .and_then(|ascii| exif::DateTime::from_ascii(ascii).ok())
+    else {
+        return Ok(None);
+    };
+    Ok(
+        NaiveDate::from_ymd_opt(dt.year.into(), dt.month.into(), dt.day.into())
+            .and_then(|date| date.and_hms_opt(dt.hour.into(), dt.minute.into(), dt.second.into())),
+    )
+}
+
+impl RuleEngine for ExifDateRuleEngine {
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+        Ok(self
+            .try_classify(file, meta)?
+            .unwrap_or_else(|| self.fallback.clone()))
+    }
+
+    fn try_classify(&self, file: &Path, meta: &FileMeta) -> Result<Option<String>, OrganizerError> {
+        let is_image = meta
+            .kind()?
+            .is_some_and(|kind| kind.matcher_type() == MatcherType::Image);
+        if is_image {
+            if let Some(taken) = exif_date_taken(file)? {
+                return Ok(Some(taken.format(&self.format).to_string()));
+            }
+        }
+        Ok(meta
+            .modified
+            .map(|t| DateTime::<Local>::from(t).format(&self.format).to_string()))
+    }
+
+    fn name(&self) -> &str {
+        "exif_date"
+    }
+}
+
+/* ------------------------------------------------------------------ */
+/* 5. По содержимому файла (magic bytes) — расширения могут врать      */
+/* ------------------------------------------------------------------ */
+
+/// Сколько байт читаем с начала файла для определения типа
+const SNIFF_LEN: usize = 8 * 1024;
+
+fn default_mime_groups() -> BTreeMap<String, String> {
+    [
+        ("image", "Images"),
+        ("video", "Videos"),
+        ("audio", "Audio"),
+        ("archive", "Archives"),
+        ("doc", "Documents"),
+        ("book", "Documents"),
+        ("text", "Documents"),
+        ("font", "Fonts"),
+        ("app", "Applications"),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_string(), v.to_string()))
+    .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MimeRuleEngine {
+    /// Ключ — полный MIME ("application/pdf") или группа
+    /// (image, video, audio, archive, doc, book, text, font, app)
+    #[serde(default = "default_mime_groups")]
+    pub groups: BTreeMap<String, String>,
+}
+
+impl Default for MimeRuleEngine {
+    fn default() -> Self {
+        Self {
+            groups: default_mime_groups(),
+        }
+    }
+}
+
+/// Определяет тип по первым байтам файла; `None`, если тип не распознан
+fn sniff(file: &Path) -> std::io::Result<Option<infer::Type>> {
+    let mut buf = Vec::with_capacity(SNIFF_LEN);
+    fs::File::open(file)?
+        .take(SNIFF_LEN as u64)
+        .read_to_end(&mut buf)?;
+    Ok(infer::get(&buf))
+}
+
+impl MimeRuleEngine {
+    fn target_for(&self, kind: &infer::Type) -> Option<String> {
+        let group = match kind.matcher_type() {
+            MatcherType::App => "app",
+            MatcherType::Archive => "archive",
+            MatcherType::Audio => "audio",
+            MatcherType::Book => "book",
+            MatcherType::Doc => "doc",
+            MatcherType::Font => "font",
+            MatcherType::Image => "image",
+            MatcherType::Text => "text",
+            MatcherType::Video => "video",
+            MatcherType::Custom => "custom",
+        };
+        self.groups
+            .get(kind.mime_type())
+            .or_else(|| self.groups.get(group))
+            .cloned()
+    }
+}
+
+impl RuleEngine for MimeRuleEngine {
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+        match self.try_classify(file, meta)? {
+            Some(target) => Ok(target),
+            None => ExtensionRuleEngine::default().classify(file, meta),
+        }
+    }
+
+    /// Без запасного варианта по расширению: тип не распознан — нет мнения
+    fn try_classify(
+        &self,
+        _file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Option<String>, OrganizerError> {
+        Ok(meta.kind()?.and_then(|kind| self.target_for(&kind)))
+    }
+
+    fn name(&self) -> &str {
+        "mime"
+    }
+}
+
+/* ------------------------------------------------------------------ */
+/* 6. По префиксу имени — серии снимков (IMG_001, IMG_002 → IMG)       */
+/* ------------------------------------------------------------------ */
+
+fn default_prefix_delimiter() -> String {
+    "_".to_string()
+}
+
+fn default_prefix_segments() -> usize {
+    1
+}
+
+fn default_prefix_fallback() -> String {
+    "no_prefix".to_string()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PrefixRuleEngine {
+    /// Разделитель частей имени (без расширения)
+    #[serde(default = "default_prefix_delimiter")]
+    pub delimiter: String,
+    /// Сколько первых частей берём в имя папки (`IMG_2024_001`, 2 → "IMG_2024")
+    #[serde(default = "default_prefix_segments")]
+    pub segments: usize,
+    /// Если задано — вместо разделителя берём первые N символов имени
+    #[serde(default)]
+    pub chars: Option<usize>,
+    /// Папка для файлов с пустым именем
+    #[serde(default = "default_prefix_fallback")]
+    pub fallback: String,
+}
+
+impl Default for PrefixRuleEngine {
+    fn default() -> Self {
+        Self {
+            delimiter: default_prefix_delimiter(),
+            segments: default_prefix_segments(),
+            chars: None,
+            fallback: default_prefix_fallback(),
+        }
+    }
+}
+
+impl PrefixRuleEngine {
+    pub fn validate(&self) -> Result<(), OrganizerError> {
+        if self.chars.is_none() && self.delimiter.is_empty() {
+            return Err(OrganizerError::Other(
+                "Prefix engine needs a non-empty delimiter or `chars`".into(),
+            ));
+        }
+        if self.segments == 0 || self.chars == Some(0) {
+            return Err(OrganizerError::Other(
+                "Prefix engine `segments`/`chars` must be at least 1".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl RuleEngine for PrefixRuleEngine {
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+        Ok(self
+            .try_classify(file, meta)?
+            .unwrap_or_else(|| self.fallback.clone()))
+    }
+
+    fn try_classify(
+        &self,
+        file: &Path,
+        _meta: &FileMeta,
+    ) -> Result<Option<String>, OrganizerError> {
+        let stem = file
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or_default();
+        let prefix = match self.chars {
+            Some(n) => stem.chars().take(n).collect::<String>(),
+            None => stem
+                .split(self.delimiter.as_str())
+                .take(self.segments)
+                .collect::<Vec<_>>()
+                .join(&self.delimiter),
+        };
+        Ok((!prefix.is_empty()).then_some(prefix))
+    }
+
+    fn name(&self) -> &str {
+        "prefix"
+    }
+}
+
+/* ------------------------------------------------------------------ */
+/* 7. По группам типов — images, video, documents…                     */
+/* ------------------------------------------------------------------ */
+
+fn default_groups() -> BTreeMap<String, Vec<String>> {
+    [
+        (
+            "images",
+            "jpg jpeg png gif bmp tif tiff webp heic heif svg ico raw cr2 nef arw dng",
+        ),
+        ("video", "mp4 m4v mkv avi mov wmv flv webm mpg mpeg 3gp"),
+        ("audio", "mp3 wav flac aac ogg oga m4a wma opus"),
+        (
+            "documents",
+            "pdf doc docx odt rtf txt md xls xlsx ods csv ppt pptx odp epub",
+        ),
+        ("archives", "zip rar 7z tar gz tgz bz2 xz zst iso"),
+        (
+            "code",
+            "rs py js ts c h cpp hpp java go rb sh html css json toml yaml yml xml",
+        ),
+        ("applications", "exe msi dmg pkg deb rpm apk appimage"),
+    ]
+    .into_iter()
+    .map(|(group, exts)| {
+        let exts = exts.split(' ').map(str::to_string).collect();
+        (group.to_string(), exts)
+    })
+    .collect()
+}
+
+fn default_group_fallback() -> String {
+    "other".to_string()
+}
+
+/// Раскладывает по крупным группам вместо отдельных расширений
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GroupRuleEngine {
+    /// Группа → расширения (без точки, регистр не важен). Заданный в
+    /// rules.json список полностью заменяет встроенный.
+    #[serde(default = "default_groups")]
+    pub groups: BTreeMap<String, Vec<String>>,
+    /// Папка для расширений, не попавших ни в одну группу
+    #[serde(default = "default_group_fallback")]
+    pub fallback: String,
+}
+
+impl Default for GroupRuleEngine {
+    fn default() -> Self {
+        Self {
+            groups: default_groups(),
+            fallback: default_group_fallback(),
+        }
+    }
+}
+
+impl GroupRuleEngine {
+    /// Группа расширения `ext`; при повторах побеждает первая по алфавиту
+    pub fn group_of(&self, ext: &str) -> Option<&str> {
+        self.groups
+            .iter()
+            .find(|(_, exts)| exts.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+            .map(|(group, _)| group.as_str())
+    }
+}
+
+impl RuleEngine for GroupRuleEngine {
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+        Ok(self
+            .try_classify(file, meta)?
+            .unwrap_or_else(|| self.fallback.clone()))
+    }
+
+    fn try_classify(
+        &self,
+        file: &Path,
+        _meta: &FileMeta,
+    ) -> Result<Option<String>, OrganizerError> {
+        Ok(file
             .extension()
             .and_then(|e| e.to_str())
-            .map(|e| e.to_ascii_lowercase())
-            .unwrap_or_default();
+            .and_then(|ext| self.group_of(ext))
+            .map(str::to_string))
+    }
+
+    fn name(&self) -> &str {
+        "group"
+    }
+}
+
+/* ------------------------------------------------------------------ */
+/* 8. Цепочка движков — первый, у кого есть мнение                     */
+/* ------------------------------------------------------------------ */
+
+fn default_chain_fallback() -> String {
+    "other".to_string()
+}
+
+/// Спрашивает движки по порядку; результат — первый `try_classify`,
+/// вернувший `Some`. Если мнения нет ни у кого — `fallback`.
+pub struct ChainRuleEngine {
+    pub engines: Vec<Box<dyn RuleEngine>>,
+    pub fallback: String,
+}
 
-        for rule in &self.rules {
-            for token in rule.pattern.split('|') {
-                if token.trim().eq_ignore_ascii_case(&ext) {
-                    return rule.target_dir.clone();
+impl RuleEngine for ChainRuleEngine {
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+        Ok(self
+            .try_classify(file, meta)?
+            .unwrap_or_else(|| self.fallback.clone()))
+    }
+
+    fn try_classify(&self, file: &Path, meta: &FileMeta) -> Result<Option<String>, OrganizerError> {
+        for engine in &self.engines {
+            if let Some(target) = engine.try_classify(file, meta)? {
+                return Ok(Some(target));
+            }
+        }
+        Ok(None)
+    }
+
+    fn classify_target(
+        &self,
+        file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Classification, OrganizerError> {
+        Ok(self
+            .try_classify_target(file, meta)?
+            .unwrap_or_else(|| Classification::fallback(self.fallback.clone())))
+    }
+
+    fn try_classify_target(
+        &self,
+        file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Option<Classification>, OrganizerError> {
+        for engine in &self.engines {
+            if let Some(target) = engine.try_classify_target(file, meta)? {
+                return Ok(Some(target));
+            }
+        }
+        Ok(None)
+    }
+
+    fn name(&self) -> &str {
+        "chain"
+    }
+}
+
+/// Описание цепочки в rules.json: `{"engine": "chain", "engines": [...]}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainConfig {
+    pub engines: Vec<RulesConfig>,
+    #[serde(default = "default_chain_fallback")]
+    pub fallback: String,
+}
+
+/* ------------------------------------------------------------------ */
+/* 9. Композиция движков — 2024/Images                                 */
+/* ------------------------------------------------------------------ */
+
+fn default_composite_separator() -> String {
+    "/".to_string()
+}
+
+/// Склеивает ответы всех движков по порядку через `separator`: дата, затем
+/// расширение → "2024/jpg". Движок без мнения даёт свою fallback‑папку,
+/// пустые части пропускаются.
+pub struct CompositeRuleEngine {
+    pub engines: Vec<Box<dyn RuleEngine>>,
+    pub separator: String,
+}
+
+impl CompositeRuleEngine {
+    /// Склеенная папка, корень первого движка, который его задал, и было ли
+    /// мнение хотя бы у одного движка
+    fn compose(
+        &self,
+        file: &Path,
+        meta: &FileMeta,
+    ) -> Result<(Classification, bool), OrganizerError> {
+        let mut root = None;
+        let mut parts = Vec::with_capacity(self.engines.len());
+        let mut rules = Vec::with_capacity(self.engines.len());
+        let mut has_opinion = false;
+        for engine in &self.engines {
+            let part = match engine.try_classify_target(file, meta)? {
+                Some(part) => {
+                    has_opinion = true;
+                    part
                 }
+                None => engine.classify_target(file, meta)?,
+            };
+            if root.is_none() {
+                root = part.root;
+            }
+            if !part.subdir.is_empty() {
+                parts.push(part.subdir);
             }
+            rules.extend(part.rule);
         }
-        self.fallback.clone()
+        let subdir = parts.join(&self.separator);
+        let rule = Some(rules.join(" + ")).filter(|rule| !rule.is_empty());
+        Ok((Classification { root, subdir, rule }, has_opinion))
+    }
+}
+
+impl RuleEngine for CompositeRuleEngine {
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+        Ok(self.compose(file, meta)?.0.subdir)
+    }
+
+    /// Нет мнения, только если его нет ни у одного из движков
+    fn try_classify(&self, file: &Path, meta: &FileMeta) -> Result<Option<String>, OrganizerError> {
+        Ok(self
+            .try_classify_target(file, meta)?
+            .map(|target| target.subdir))
+    }
+
+    fn classify_target(
+        &self,
+        file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Classification, OrganizerError> {
+        Ok(self.compose(file, meta)?.0)
+    }
+
+    fn try_classify_target(
+        &self,
+        file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Option<Classification>, OrganizerError> {
+        let (target, has_opinion) = self.compose(file, meta)?;
+        Ok(has_opinion.then_some(target))
+    }
+
+    fn name(&self) -> &str {
+        "composite"
     }
 }
 
+/// Описание композиции в rules.json:
+/// `{"engine": "composite", "engines": [{"engine": "date", "format": "%Y"}, {"engine": "extension"}]}`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CompositeConfig {
+    pub engines: Vec<RulesConfig>,
+    #[serde(default = "default_composite_separator")]
+    pub separator: String,
+}
+
 /* ------------------------------------------------------------------ */
-/* 3. Blanket‑impl, чтобы Box<T> и Arc<T> тоже удовлетворяли RuleEngine*/
+/* 10. Шаблон пути из метаданных — {year}/{ext}                        */
+/* ------------------------------------------------------------------ */
+
+fn default_template_unknown() -> String {
+    "unknown".to_string()
+}
+
+/// Папка из шаблона с подстановками: `{ext}`, `{year}`, `{month}`, `{mime}`,
+/// `{size_bucket}` ("{year}/{ext}" → "2023/pdf"). `{mime}` даёт две папки
+/// ("image/jpeg"); чего у файла нет, заменяется на `unknown`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TemplateRuleEngine {
+    pub template: String,
+    #[serde(default = "default_template_unknown")]
+    pub unknown: String,
+}
+
+/// Кусок разобранного шаблона
+enum TemplatePart<'a> {
+    Text(&'a str),
+    Field(&'a str),
+}
+
+const TEMPLATE_FIELDS: [&str; 5] = ["ext", "year", "month", "mime", "size_bucket"];
+
+impl TemplateRuleEngine {
+    pub fn new(template: impl Into<String>) -> Self {
+        Self {
+            template: template.into(),
+            unknown: default_template_unknown(),
+        }
+    }
+
+    /// Неизвестные подстановки и непарные скобки — ошибка при загрузке, а не на каждом файле
+    pub fn validate(&self) -> Result<(), OrganizerError> {
+        if self.template.trim().is_empty() {
+            return Err(OrganizerError::Other(
+                "Template engine needs a template".into(),
+            ));
+        }
+        self.parts().map(|_| ())
+    }
+
+    fn parts(&self) -> Result<Vec<TemplatePart<'_>>, OrganizerError> {
+        let invalid = |reason: String| {
+            OrganizerError::Other(format!("Invalid template {:?}: {}", self.template, reason))
+        };
+        let mut parts = Vec::new();
+        let mut rest = self.template.as_str();
+        while !rest.is_empty() {
+            let Some(open) = rest.find(['{', '}']) else {
+                parts.push(TemplatePart::Text(rest));
+                break;
+            };
+            if rest[open..].starts_with('}') {
+                return Err(invalid("unmatched '}'".into()));
+            }
+            let len = rest[open..]
+                .find('}')
+                .ok_or_else(|| invalid("unclosed '{'".into()))?;
+            let field = &rest[open + 1..open + len];
+            if !TEMPLATE_FIELDS.contains(&field) {
+                return Err(invalid(format!(
+                    "unknown placeholder {{{}}}, expected one of {}",
+                    field,
+                    TEMPLATE_FIELDS.map(|f| format!("{{{}}}", f)).join(", ")
+                )));
+            }
+            if open > 0 {
+                parts.push(TemplatePart::Text(&rest[..open]));
+            }
+            parts.push(TemplatePart::Field(field));
+            rest = &rest[open + len + 1..];
+        }
+        Ok(parts)
+    }
+
+    fn field(
+        &self,
+        field: &str,
+        file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Option<String>, OrganizerError> {
+        let modified = meta.modified.map(DateTime::<Local>::from);
+        Ok(match field {
+            "ext" => file
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(str::to_ascii_lowercase),
+            "year" => modified.map(|t| t.format("%Y").to_string()),
+            "month" => modified.map(|t| t.format("%m").to_string()),
+            "mime" => meta.mime()?.map(str::to_string),
+            "size_bucket" => Some(size_bucket(meta.size).to_string()),
+            _ => None,
+        })
+    }
+}
+
+/// Грубая категория размера для `{size_bucket}`
+fn size_bucket(size: u64) -> &'static str {
+    const MIB: u64 = 1024 * 1024;
+    match size {
+        0 => "empty",
+        s if s < MIB => "small",
+        s if s < 100 * MIB => "medium",
+        s if s < 1024 * MIB => "large",
+        _ => "huge",
+    }
+}
+
+impl RuleEngine for TemplateRuleEngine {
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+        let mut target = String::new();
+        for part in self.parts()? {
+            match part {
+                TemplatePart::Text(text) => target.push_str(text),
+                TemplatePart::Field(field) => match self.field(field, file, meta)? {
+                    Some(value) if !value.is_empty() => target.push_str(&value),
+                    _ => target.push_str(&self.unknown),
+                },
+            }
+        }
+        Ok(target)
+    }
+
+    fn name(&self) -> &str {
+        "template"
+    }
+}
+
+/* ------------------------------------------------------------------ */
+/* 11. По возрасту файла — Recent / Archive                            */
+/* ------------------------------------------------------------------ */
+
+fn default_age_days() -> u64 {
+    30
+}
+
+fn default_age_recent() -> String {
+    "Recent".to_string()
+}
+
+fn default_age_archive() -> String {
+    "Archive".to_string()
+}
+
+/// Грубее `DateRuleEngine`: файлы, изменённые за последние `days` дней,
+/// идут в `recent`, более старые — в `archive`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AgeRuleEngine {
+    #[serde(default = "default_age_days")]
+    pub days: u64,
+    #[serde(default = "default_age_recent")]
+    pub recent: String,
+    #[serde(default = "default_age_archive")]
+    pub archive: String,
+    /// Куда класть файлы, у которых нельзя узнать время изменения
+    #[serde(default = "default_date_fallback")]
+    pub fallback: String,
+}
+
+impl Default for AgeRuleEngine {
+    fn default() -> Self {
+        Self {
+            days: default_age_days(),
+            recent: default_age_recent(),
+            archive: default_age_archive(),
+            fallback: default_date_fallback(),
+        }
+    }
+}
+
+impl AgeRuleEngine {
+    pub fn validate(&self) -> Result<(), OrganizerError> {
+        if self.recent.trim().is_empty() || self.archive.trim().is_empty() {
+            return Err(OrganizerError::Other(
+                "Age engine needs \"recent\" and \"archive\" folders".into(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl RuleEngine for AgeRuleEngine {
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+        Ok(self
+            .try_classify(file, meta)?
+            .unwrap_or_else(|| self.fallback.clone()))
+    }
+
+    fn try_classify(
+        &self,
+        _file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Option<String>, OrganizerError> {
+        let threshold = Duration::from_secs(self.days.saturating_mul(24 * 60 * 60));
+        Ok(meta.modified.map(|modified| {
+            // время из будущего (сбитые часы) — тоже «свежий» файл
+            let age = SystemTime::now()
+                .duration_since(modified)
+                .unwrap_or_default();
+            if age < threshold {
+                self.recent.clone()
+            } else {
+                self.archive.clone()
+            }
+        }))
+    }
+
+    fn name(&self) -> &str {
+        "age"
+    }
+}
+
+/* ------------------------------------------------------------------ */
+/* 12. Blanket‑impl, чтобы Box<T> и Arc<T> удовлетворяли RuleEngine */
 /* ------------------------------------------------------------------ */
 
 use std::sync::Arc;
 
 impl<T: RuleEngine + ?Sized> RuleEngine for Box<T> {
-    fn classify(&self, file: &Path) -> String {
-        (**self).classify(file)
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+        (**self).classify(file, meta)
+    }
+
+    fn try_classify(&self, file: &Path, meta: &FileMeta) -> Result<Option<String>, OrganizerError> {
+        (**self).try_classify(file, meta)
+    }
+
+    fn classify_target(
+        &self,
+        file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Classification, OrganizerError> {
+        (**self).classify_target(file, meta)
+    }
+
+    fn try_classify_target(
+        &self,
+        file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Option<Classification>, OrganizerError> {
+        (**self).try_classify_target(file, meta)
+    }
+
+    fn name(&self) -> &str {
+        (**self).name()
     }
 }
 
 impl<T: RuleEngine + ?Sized> RuleEngine for Arc<T> {
-    fn classify(&self, file: &Path) -> String {
-        (**self).classify(file)
+    fn classify(&self, file: &Path, meta: &FileMeta) -> Result<String, OrganizerError> {
+        (**self).classify(file, meta)
+    }
+
+    fn try_classify(&self, file: &Path, meta: &FileMeta) -> Result<Option<String>, OrganizerError> {
+        (**self).try_classify(file, meta)
+    }
+
+    fn classify_target(
+        &self,
+        file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Classification, OrganizerError> {
+        (**self).classify_target(file, meta)
+    }
+
+    fn try_classify_target(
+        &self,
+        file: &Path,
+        meta: &FileMeta,
+    ) -> Result<Option<Classification>, OrganizerError> {
+        (**self).try_classify_target(file, meta)
+    }
+
+    fn name(&self) -> &str {
+        (**self).name()
+    }
+}
+
+/* ------------------------------------------------------------------ */
+/* 13. Выбор движка по полю "engine" в rules.json                      */
+/* ------------------------------------------------------------------ */
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "engine", rename_all = "snake_case")]
+pub enum RulesConfig {
+    Extension(ExtensionRuleEngine),
+    Custom(CustomRuleEngine),
+    Date(DateRuleEngine),
+    ExifDate(ExifDateRuleEngine),
+    Mime(MimeRuleEngine),
+    Prefix(PrefixRuleEngine),
+    Group(GroupRuleEngine),
+    Template(TemplateRuleEngine),
+    Age(AgeRuleEngine),
+    Chain(ChainConfig),
+    Composite(CompositeConfig),
+}
+
+impl RulesConfig {
+    /// Разбирает rules.json. Файлы без поля "engine" считаются `custom`,
+    /// чтобы старые наборы правил продолжали работать.
+    pub fn from_json(text: &str) -> Result<Self, OrganizerError> {
+        let mut value: serde_json::Value = serde_json::from_str(text)?;
+        if let Some(obj) = value.as_object_mut() {
+            let engine = obj
+                .entry("engine")
+                .or_insert_with(|| serde_json::Value::String("custom".into()));
+            if engine == "custom" {
+                Self::check_rules(obj.get("rules"))?;
+            }
+        }
+        let config: Self = serde_json::from_value(value)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Разбирает правила по одному, чтобы ошибка (опечатка в имени поля,
+    /// пропущенный `pattern`) указывала на номер правила, а не на строку/колонку
+    fn check_rules(rules: Option<&serde_json::Value>) -> Result<(), OrganizerError> {
+        let Some(rules) = rules.and_then(|r| r.as_array()) else {
+            return Ok(());
+        };
+        for (i, rule) in rules.iter().enumerate() {
+            serde_json::from_value::<CustomRule>(rule.clone())
+                .map_err(|e| OrganizerError::Other(format!("rule #{}: {}", i + 1, e)))?;
+        }
+        Ok(())
+    }
+
+    /// Некритичные замечания к правилам (см. `CustomRuleEngine::validate`)
+    pub fn warnings(&self) -> Vec<RuleWarning> {
+        match self {
+            RulesConfig::Custom(engine) => engine.validate(),
+            RulesConfig::Chain(ChainConfig { engines, .. })
+            | RulesConfig::Composite(CompositeConfig { engines, .. }) => engines
+                .iter()
+                .enumerate()
+                .flat_map(|(k, config)| {
+                    let kind = self.kind();
+                    config.warnings().into_iter().map(move |w| RuleWarning {
+                        index: w.index,
+                        reason: format!("{} ({} engine #{})", w.reason, kind, k + 1),
+                    })
+                })
+                .collect(),
+            RulesConfig::Extension(_)
+            | RulesConfig::Date(_)
+            | RulesConfig::ExifDate(_)
+            | RulesConfig::Mime(_)
+            | RulesConfig::Prefix(_)
+            | RulesConfig::Group(_)
+            | RulesConfig::Template(_)
+            | RulesConfig::Age(_) => Vec::new(),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), OrganizerError> {
+        match self {
+            RulesConfig::Extension(_) => Ok(()),
+            RulesConfig::Custom(engine) => engine.compile(),
+            RulesConfig::Date(engine) => engine.validate(),
+            RulesConfig::ExifDate(engine) => engine.validate(),
+            RulesConfig::Mime(_) => Ok(()),
+            RulesConfig::Prefix(engine) => engine.validate(),
+            RulesConfig::Group(_) => Ok(()),
+            RulesConfig::Template(engine) => engine.validate(),
+            RulesConfig::Age(engine) => engine.validate(),
+            RulesConfig::Chain(ChainConfig { engines, .. })
+            | RulesConfig::Composite(CompositeConfig { engines, .. }) => {
+                let kind = self.kind();
+                if engines.is_empty() {
+                    return Err(OrganizerError::Other(format!(
+                        "{} engine needs at least one engine",
+                        if kind == "chain" {
+                            "Chain"
+                        } else {
+                            "Composite"
+                        }
+                    )));
+                }
+                for (k, config) in engines.iter().enumerate() {
+                    config.validate().map_err(|e| match e {
+                        OrganizerError::Other(msg) => {
+                            OrganizerError::Other(format!("{} engine #{}: {}", kind, k + 1, msg))
+                        }
+                        e => e,
+                    })?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    pub fn into_engine(self) -> Box<dyn RuleEngine> {
+        match self {
+            RulesConfig::Extension(engine) => Box::new(engine),
+            RulesConfig::Custom(engine) => Box::new(engine),
+            RulesConfig::Date(engine) => Box::new(engine),
+            RulesConfig::ExifDate(engine) => Box::new(engine),
+            RulesConfig::Mime(engine) => Box::new(engine),
+            RulesConfig::Prefix(engine) => Box::new(engine),
+            RulesConfig::Group(engine) => Box::new(engine),
+            RulesConfig::Template(engine) => Box::new(engine),
+            RulesConfig::Age(engine) => Box::new(engine),
+            RulesConfig::Chain(chain) => Box::new(ChainRuleEngine {
+                engines: chain
+                    .engines
+                    .into_iter()
+                    .map(RulesConfig::into_engine)
+                    .collect(),
+                fallback: chain.fallback,
+            }),
+            RulesConfig::Composite(composite) => Box::new(CompositeRuleEngine {
+                engines: composite
+                    .engines
+                    .into_iter()
+                    .map(RulesConfig::into_engine)
+                    .collect(),
+                separator: composite.separator,
+            }),
+        }
+    }
+
+    /// "chain" или "composite", для сообщений об ошибках во вложенных движках
+    fn kind(&self) -> &'static str {
+        match self {
+            RulesConfig::Composite(_) => "composite",
+            _ => "chain",
+        }
     }
 }
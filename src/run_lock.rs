@@ -0,0 +1,94 @@
+//! Advisory lock that keeps two runs (CLI, GUI, watch) from moving files and
+//! writing the same history at once
+use crate::errors::OrganizerError;
+use chrono::Local;
+use fs2::FileExt;
+use log::{info, warn};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// Held for the duration of a run; dropping it releases the lock
+pub struct RunLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl RunLock {
+    /// Takes `path` exclusively. If another process holds it, fails — or with
+    /// `wait` blocks until it is released. The OS drops the lock of a crashed
+    /// process, so a file still naming an owner is reported as stale and taken over.
+    pub fn acquire(path: &Path, wait: bool) -> Result<Self, OrganizerError> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent).map_err(|e| OrganizerError::io_at(parent, e))?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(|e| OrganizerError::io_at(path, e))?;
+
+        match file.try_lock_exclusive() {
+            Ok(()) => {
+                let owner = read_owner(&mut file);
+                if !owner.is_empty() {
+                    warn!("Taking over stale lock {:?} left by {}", path, owner);
+                }
+            }
+            Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                let owner = read_owner(&mut file);
+                let owner = if owner.is_empty() {
+                    "unknown process".to_string()
+                } else {
+                    owner
+                };
+                if !wait {
+                    return Err(OrganizerError::RunInProgress {
+                        owner,
+                        lock: path.to_path_buf(),
+                    });
+                }
+                info!("Waiting for another run to finish ({})", owner);
+                file.lock_exclusive()
+                    .map_err(|e| OrganizerError::io_at(path, e))?;
+            }
+            Err(e) => return Err(OrganizerError::io_at(path, e)),
+        }
+
+        let owner = format!(
+            "pid {}, started {}",
+            std::process::id(),
+            Local::now().format("%Y-%m-%d %H:%M:%S")
+        );
+        file.set_len(0)
+            .and_then(|_| file.seek(SeekFrom::Start(0)))
+            .and_then(|_| file.write_all(owner.as_bytes()))
+            .map_err(|e| OrganizerError::io_at(path, e))?;
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // the file stays; an empty one tells the next run we finished cleanly
+        if let Err(e) = self.file.set_len(0) {
+            warn!("Cannot clear lock file {:?}: {}", self.path, e);
+        }
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+/// Owner line written by `acquire`; empty if none or unreadable (Windows
+/// denies reading a file another process has locked)
+fn read_owner(file: &mut File) -> String {
+    let mut owner = String::new();
+    let _ = file.read_to_string(&mut owner);
+    owner.trim().to_string()
+}
@@ -1,15 +1,48 @@
 // src/ui_cli.rs
 
-use crate::{
-    history::HistoryManager,
-    logger::setup_logging,
-    organizer::{Organizer, OrganizerConfig},
-    rules::{CustomRuleEngine, ExtensionRuleEngine, RuleEngine},
+use crate::config_file::{load_config, ConfigFile};
+use crate::desktop_notify::{notify_failed, notify_finished};
+use crate::workspace::{default_history, default_log_file};
+use chrono::{DateTime, Local, NaiveDate, SecondsFormat, Utc};
+use clap::{
+    parser::ValueSource, ArgAction, ArgMatches, Args, CommandFactory, FromArgMatches, Parser,
+    Subcommand, ValueEnum,
 };
-use clap::{Parser, Subcommand};
+use clap_complete::Shell;
 use dialoguer::{theme::ColorfulTheme, Select};
-use log::info;
-use std::path::PathBuf;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{info, warn, LevelFilter};
+use owo_colors::{OwoColorize, Stream, Style};
+use serde::Serialize;
+#[cfg(feature = "testing")]
+use smart_file_organizer::organizer::SimulatedFailure;
+use smart_file_organizer::{
+    history::{HistoryManager, MovedFile, TransferKind},
+    logger::{adjust_level, parse_level, setup_logging, LogFormat, LogOptions, DEFAULT_LOG_KEEP},
+    organizer::{
+        validate_conflict_template, ConflictChoice, ConflictStrategy, InvalidNameStrategy,
+        LinkMode, OrganizeReport, Organizer, OrganizerConfig, PlanAction, PlannedMove, TreatDirs,
+        UndoAction, UnicodeForm,
+    },
+    profiles::{list_profiles, profile_path, save_profile},
+    rules::{
+        Classification, ExtensionRuleEngine, FileMeta, GroupRuleEngine, RuleEngine, RulesConfig,
+    },
+    units::{format_size, parse_age, parse_size},
+    watch::watch,
+};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+/// Env variable that overrides the default history file for all commands
+const HISTORY_ENV: &str = "SMART_ORGANIZER_HISTORY";
 
 #[derive(Parser, Debug)]
 #[command(
@@ -22,106 +55,1443 @@ pub struct CliArgs {
     #[arg(long)]
     pub gui: bool,
 
+    /// Log line format for stdout (and the log file, unless --file-log-format is given)
+    #[arg(long, value_enum, global = true, default_value_t)]
+    pub log_format: LogFormat,
+
+    /// Log line format for the log file
+    #[arg(long, value_enum, global = true)]
+    pub file_log_format: Option<LogFormat>,
+
+    /// Minimum level to log: error, warn, info, debug or trace
+    #[arg(long, global = true, default_value = "info", value_parser = parse_level)]
+    pub log_level: LevelFilter,
+
+    /// Log more (repeatable: -v = debug, -vv = trace)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Log less (repeatable: -q = warn, -qq = error)
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub quiet: u8,
+
+    /// Don't log to stdout and don't print the organize summary; the log file still gets everything.
+    /// Not `--quiet`: that is `-q`, which only lowers the log level
+    #[arg(long, global = true)]
+    pub silent: bool,
+
+    /// Log file [default: organizer.log in the data folder]
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+
+    /// Rotate the log file when it exceeds this size (e.g. 10MB)
+    #[arg(long, global = true, value_parser = parse_size, default_value = "10MB")]
+    pub max_log_size: u64,
+
+    /// Number of rotated log files to keep
+    #[arg(long, global = true, default_value_t = DEFAULT_LOG_KEEP)]
+    pub log_keep: usize,
+
+    /// Abort on a corrupt history file instead of setting it aside and starting over
+    #[arg(long, global = true)]
+    pub strict_history: bool,
+
+    /// Keep at most this many moves in the history, forgetting the oldest
+    #[arg(long, global = true)]
+    pub history_limit: Option<usize>,
+
+    /// Forget moves older than this (e.g. 30d, 12h, 2w) when recording new ones
+    #[arg(long, global = true, value_parser = parse_age)]
+    pub history_max_age: Option<Duration>,
+
+    /// Read defaults from this file instead of ./smart-organizer.toml or the config folder
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Ignore smart-organizer.toml files
+    #[arg(long, global = true, conflicts_with = "config")]
+    pub no_config: bool,
+
     #[command(subcommand)]
     pub command: Option<Commands>,
 }
 
+impl CliArgs {
+    pub fn log_options(&self) -> LogOptions {
+        LogOptions {
+            level: adjust_level(self.log_level, self.verbose, self.quiet),
+            // `organize --json` keeps stdout for the summary object
+            stdout: !self.silent
+                && !matches!(&self.command, Some(Commands::Organize(organize)) if organize.json),
+            file: !self.dry_run(),
+            stdout_format: self.log_format,
+            file_format: self.file_log_format.unwrap_or(self.log_format),
+            max_log_bytes: self.max_log_size,
+            log_keep: self.log_keep,
+        }
+    }
+
+    /// Whether the command only previews; it must not write anything, the
+    /// log file and the migration of an old `.smart_organizer` included
+    pub fn dry_run(&self) -> bool {
+        match &self.command {
+            Some(Commands::Organize(organize) | Commands::Watch { organize, .. }) => {
+                organize.dry_run
+            }
+            Some(Commands::UndoAll { dry_run, .. }) => *dry_run,
+            _ => false,
+        }
+    }
+
+    pub fn log_file(&self) -> PathBuf {
+        self.log_file.clone().unwrap_or_else(default_log_file)
+    }
+
+    fn history_options(&self) -> HistoryOptions {
+        HistoryOptions {
+            strict: self.strict_history,
+            limit: self.history_limit,
+            max_age: self.history_max_age,
+        }
+    }
+}
+
+/// Global history flags, applied to every history file a command opens
+#[derive(Debug, Clone, Copy)]
+struct HistoryOptions {
+    strict: bool,
+    limit: Option<usize>,
+    max_age: Option<Duration>,
+}
+
+impl HistoryOptions {
+    fn open(self, path: PathBuf) -> HistoryManager {
+        HistoryManager::new(path)
+            .with_strict(self.strict)
+            .with_limit(self.limit)
+            .with_max_age(self.max_age)
+    }
+}
+
+/// Flags of the `organize` subcommand
+#[derive(Args, Debug, Default)]
+pub struct OrganizeArgs {
+    /// Source folder; repeat it to sort several folders into one destination
+    #[arg(short, long)]
+    src: Vec<PathBuf>,
+    #[arg(short, long)]
+    dst: Option<PathBuf>,
+    #[arg(long)]
+    dry_run: bool,
+    /// Shortcut for `--on-conflict overwrite`
+    #[arg(long, conflicts_with = "on_conflict")]
+    overwrite: bool,
+    /// What to do when the target name is taken
+    #[arg(long, value_enum, default_value_t)]
+    on_conflict: ConflictStrategy,
+    /// Name for renamed conflicts from {stem}, {n} and {ext} [default: {stem}_({n}).{ext}]
+    #[arg(long, value_parser = parse_conflict_template)]
+    conflict_template: Option<String>,
+    /// Wait for another run using the same history to finish instead of failing
+    #[arg(long)]
+    wait_lock: bool,
+    /// Retry a file held open by another program this many times (1 s apart) before skipping it
+    #[arg(long, default_value_t = 0)]
+    retry_locked: u32,
+    /// Ask what to do on every name conflict
+    #[arg(long, conflicts_with_all = ["overwrite", "on_conflict"])]
+    interactive: bool,
+    /// What to do with target names the OS rejects (illegal characters, too long)
+    #[arg(long, value_enum, default_value_t)]
+    on_invalid_name: InvalidNameStrategy,
+    /// move-top-level-dirs moves each folder in the source whose name a rule matches as a whole
+    #[arg(long, value_enum, default_value_t)]
+    treat_dirs: TreatDirs,
+    /// Give destination names this Unicode form, e.g. nfc for files from a macOS volume
+    #[arg(long, value_enum)]
+    normalize_unicode: Option<UnicodeForm>,
+    #[arg(long)]
+    rules: Option<PathBuf>,
+    /// Use a rules profile saved with `rules save`
+    #[arg(long, conflicts_with = "rules")]
+    profile: Option<String>,
+    /// History file recording the moves for undo [default: history.json in the data folder]
+    #[arg(long, env = HISTORY_ENV)]
+    history: Option<PathBuf>,
+    /// Refuse to run if the rules file has any warnings (duplicate, shadowed or empty rules)
+    #[arg(long)]
+    strict_rules: bool,
+    /// Worker threads (default: number of CPUs)
+    #[arg(long)]
+    threads: Option<usize>,
+    /// Move or copy at most this much per second, e.g. 10MB (for network drives)
+    #[arg(long, value_parser = parse_size)]
+    max_rate: Option<u64>,
+    /// Stop after moving this many files (skipped and failed ones don't count); with --dry-run, plan that many
+    #[arg(long)]
+    max_files: Option<usize>,
+    /// Fail files matching GLOB with an injected error: GLOB=permission-denied|storage-full|in-use|other
+    #[cfg(feature = "testing")]
+    #[arg(long, hide = true, value_parser = parse_simulated_error)]
+    simulate_errors: Vec<(String, SimulatedFailure)>,
+    /// Write the list of planned/performed moves to a .json or .csv file
+    #[arg(long)]
+    report: Option<PathBuf>,
+    /// Write the run statistics (counts per category, bytes, conflicts, time) as JSON
+    #[arg(long)]
+    summary_json: Option<PathBuf>,
+    /// Print nothing but the run statistics as one JSON object on stdout
+    #[arg(long)]
+    json: bool,
+    /// Show a desktop notification with the counts when the run ends
+    #[arg(long)]
+    notify: bool,
+    /// Only descend this many levels below the source (1 = files directly in it)
+    #[arg(long)]
+    max_depth: Option<usize>,
+    /// Shortcut for `--max-depth 1`
+    #[arg(long, conflicts_with = "max_depth")]
+    no_recursive: bool,
+    /// Also move dotfiles and hidden/system files
+    #[arg(long)]
+    include_hidden: bool,
+    /// Only organize files matching this glob (repeatable), e.g. '*.pdf'
+    #[arg(long)]
+    include: Vec<String>,
+    /// Never touch files matching this glob (repeatable), e.g. '**/node_modules/**'
+    #[arg(long)]
+    exclude: Vec<String>,
+    /// Also skip what the source folder's .gitignore lists (and .git);
+    /// .organizerignore there is always honored
+    #[arg(long)]
+    respect_gitignore: bool,
+    /// Only organize files of at least this size, e.g. 1 or 10MB
+    #[arg(long, value_parser = parse_size)]
+    min_size: Option<u64>,
+    /// Only organize files of at most this size, e.g. 2GB
+    #[arg(long, value_parser = parse_size)]
+    max_size: Option<u64>,
+    /// Leave zero-byte files where they are
+    #[arg(long)]
+    skip_empty: bool,
+    /// Only organize files modified within this time, e.g. 7d, 12h, 2w
+    #[arg(long, value_parser = parse_age)]
+    newer_than: Option<Duration>,
+    /// Only organize files last modified longer ago than this, e.g. 30d
+    #[arg(long, value_parser = parse_age)]
+    older_than: Option<Duration>,
+    /// Leave files modified less than this long ago, e.g. 10s; 0s moves them right away [default: 2s]
+    #[arg(long, value_parser = parse_age)]
+    min_age: Option<Duration>,
+    /// Extensions of unfinished downloads to leave alone, replacing the built-in
+    /// crdownload, part, partial, download, opdownload, filepart, tmp, !ut, aria2 (empty for none)
+    #[arg(long, value_delimiter = ',')]
+    partial_ext: Option<Vec<String>>,
+    /// Only organize these categories, e.g. 'images,documents'; without --rules
+    /// files are sorted into type groups (images, video, audio, documents, ...)
+    #[arg(long, value_delimiter = ',')]
+    groups: Vec<String>,
+    /// Keep the source subfolder structure inside each category folder
+    #[arg(long)]
+    preserve_tree: bool,
+    /// Sort files into a subfolder per first letter inside each category (pdf/R/report.pdf)
+    #[arg(long)]
+    bucket_initial: bool,
+    /// Reuse destination folders whose name differs from the category only in case (Invoices for invoices)
+    #[arg(long)]
+    merge_existing: bool,
+    /// Delete source subfolders that end up empty after the run
+    #[arg(long)]
+    cleanup: bool,
+    /// Copy files instead of moving them; originals stay where they are
+    #[arg(long)]
+    copy: bool,
+    /// Link files into the destination instead of moving them (hard needs one filesystem)
+    #[arg(long, value_enum, conflicts_with = "copy")]
+    link: Option<LinkMode>,
+    /// Don't carry the original timestamps and permissions over to copied files
+    #[arg(long)]
+    no_preserve_times: bool,
+    /// Check each copy against its source before deleting the source (cross-drive moves, --copy)
+    #[arg(long)]
+    verify: bool,
+    /// Send overwritten files (and the originals of cross-drive moves) to the trash instead of deleting them
+    #[arg(long)]
+    trash: bool,
+    /// Roll back the whole run if a fatal error (full destination, target folder can't be created) occurs
+    #[arg(long)]
+    transactional: bool,
+}
+
 #[derive(Subcommand, Debug)]
 pub enum Commands {
     /// Organize files
-    Organize {
-        #[arg(short, long)] src: Option<PathBuf>,
-        #[arg(short, long)] dst: Option<PathBuf>,
-        #[arg(long)] dry_run: bool,
-        #[arg(long)] overwrite: bool,
-        #[arg(long)] rules: Option<PathBuf>,
-    },
+    Organize(OrganizeArgs),
     /// Undo last move
     UndoLast {
-        #[arg(long, default_value = ".smart_organizer/history.json")]
+        #[arg(long, env = HISTORY_ENV, default_value_os_t = default_history())]
+        history: PathBuf,
+    },
+    /// Redo the move most recently reverted by an undo command
+    RedoLast {
+        #[arg(long, env = HISTORY_ENV, default_value_os_t = default_history())]
         history: PathBuf,
     },
     /// Undo all moves
     UndoAll {
-        #[arg(long, default_value = ".smart_organizer/history.json")]
+        /// Only list what would be undone; nothing is moved
+        #[arg(long)]
+        dry_run: bool,
+        #[arg(long, env = HISTORY_ENV, default_value_os_t = default_history())]
+        history: PathBuf,
+    },
+    /// Undo the moves of a single organize run
+    UndoSession {
+        #[arg(long)]
+        id: u64,
+        #[arg(long, env = HISTORY_ENV, default_value_os_t = default_history())]
+        history: PathBuf,
+    },
+    /// Undo the latest move of one file, given its old or its new path
+    UndoFile {
+        #[arg(long)]
+        path: PathBuf,
+        #[arg(long, env = HISTORY_ENV, default_value_os_t = default_history())]
+        history: PathBuf,
+    },
+    /// Undo moves made in a time range (RFC 3339 or YYYY-MM-DD, local time)
+    UndoRange {
+        #[arg(long, value_parser = parse_time)]
+        since: DateTime<Utc>,
+        /// Exclusive upper bound; everything since `--since` if omitted
+        #[arg(long, value_parser = parse_time)]
+        until: Option<DateTime<Utc>>,
+        #[arg(long, env = HISTORY_ENV, default_value_os_t = default_history())]
+        history: PathBuf,
+    },
+    /// List recorded organize runs
+    Sessions {
+        #[arg(long, env = HISTORY_ENV, default_value_os_t = default_history())]
         history: PathBuf,
     },
+    /// Read the recorded moves
+    History {
+        #[command(subcommand)]
+        command: HistoryCommand,
+    },
+    /// Manage named rules profiles
+    Rules {
+        #[command(subcommand)]
+        command: RulesCommand,
+    },
+    /// Keep running and organize files as they arrive in the source folder
+    Watch {
+        #[command(flatten)]
+        organize: OrganizeArgs,
+        /// Milliseconds a new file's size must stay unchanged before it is moved
+        #[arg(long, default_value_t = 2000)]
+        debounce: u64,
+    },
+    /// Check the source, destination, history and rules without moving anything
+    Doctor(OrganizeArgs),
+    /// Print the folder a file name would be sorted into, without touching any file
+    TestRule {
+        /// Rules JSON file; the built-in sorting by extension if neither this nor --profile is given
+        #[arg(long)]
+        rules: Option<PathBuf>,
+        /// Use a rules profile saved with `rules save`
+        #[arg(long, conflicts_with = "rules")]
+        profile: Option<String>,
+        /// File name, or path below the source folder, e.g. IMG_001.jpg or Screenshots/a.png
+        #[arg(long)]
+        name: PathBuf,
+    },
+    /// Print a shell completion script, e.g. `completions zsh > _smart_file_organizer`
+    Completions { shell: Shell },
 }
 
-pub fn run_cli() -> anyhow::Result<()> {
-    let args = CliArgs::parse();
+#[derive(Subcommand, Debug)]
+pub enum HistoryCommand {
+    /// Write every recorded move (from, to, time, session) as CSV or JSON
+    Export {
+        #[arg(long, value_enum, default_value_t)]
+        format: ExportFormat,
+        /// Output file; stdout if omitted
+        #[arg(long)]
+        out: Option<PathBuf>,
+        #[arg(long, env = HISTORY_ENV, default_value_os_t = default_history())]
+        history: PathBuf,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum RulesCommand {
+    /// Validate a rules file and store it under a name
+    Save { name: String, file: PathBuf },
+    /// List saved profiles
+    List,
+    /// Print a saved profile
+    Show { name: String },
+}
+
+/// Parses the command line and fills in what it leaves unset from the config
+/// file: command line > env variables > config file > built-in defaults
+pub fn parse_args() -> anyhow::Result<CliArgs> {
+    let matches = CliArgs::command().get_matches();
+    let mut args = CliArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if !args.gui && args.command.is_none() {
+        args.command = Some(default_command());
+    }
+    if args.no_config || matches!(args.command, Some(Commands::Completions { .. })) {
+        return Ok(args);
+    }
+    if let Some((path, config)) = load_config(args.config.as_deref())? {
+        apply_config(&mut args, &matches, &config)
+            .map_err(|e| anyhow::anyhow!("Invalid config file {:?}: {}", path, e))?;
+    }
+    Ok(args)
+}
+
+/// `organize` with the history from the env variable, run when no subcommand is given
+fn default_command() -> Commands {
+    Commands::Organize(OrganizeArgs {
+        history: std::env::var_os(HISTORY_ENV).map(PathBuf::from),
+        ..Default::default()
+    })
+}
+
+fn apply_config(
+    args: &mut CliArgs,
+    matches: &ArgMatches,
+    config: &ConfigFile,
+) -> anyhow::Result<()> {
+    if !set_by_user(Some(matches), "log_level") {
+        if let Some(level) = &config.log_level {
+            args.log_level = parse_level(level).map_err(|e| anyhow::anyhow!("log_level: {}", e))?;
+        }
+    }
+    if !set_by_user(Some(matches), "log_format") {
+        if let Some(format) = &config.log_format {
+            args.log_format = value_enum("log_format", format)?;
+        }
+    }
+
+    if args.history_limit.is_none() {
+        args.history_limit = config.history_limit;
+    }
+    if args.history_max_age.is_none() {
+        if let Some(age) = &config.history_max_age {
+            args.history_max_age =
+                Some(parse_age(age).map_err(|e| anyhow::anyhow!("history_max_age: {}", e))?);
+        }
+    }
+
+    let sub_matches = matches.subcommand().map(|(_, m)| m);
+    match &mut args.command {
+        Some(
+            Commands::Organize(organize)
+            | Commands::Watch { organize, .. }
+            | Commands::Doctor(organize),
+        ) => apply_organize_config(organize, sub_matches, config)?,
+        Some(
+            Commands::UndoLast { history }
+            | Commands::RedoLast { history }
+            | Commands::UndoAll { history, .. }
+            | Commands::UndoSession { history, .. }
+            | Commands::UndoFile { history, .. }
+            | Commands::UndoRange { history, .. }
+            | Commands::Sessions { history },
+        ) => {
+            if let (Some(path), false) = (&config.history, set_by_user(sub_matches, "history")) {
+                *history = path.clone();
+            }
+        }
+        Some(Commands::History {
+            command: HistoryCommand::Export { history, .. },
+        }) => {
+            let export_matches = sub_matches.and_then(|m| m.subcommand_matches("export"));
+            if let (Some(path), false) = (&config.history, set_by_user(export_matches, "history")) {
+                *history = path.clone();
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Options still unset (`None`, empty, `false`) may come from the config file;
+/// flags with a default value are checked with `set_by_user`
+fn apply_organize_config(
+    args: &mut OrganizeArgs,
+    matches: Option<&ArgMatches>,
+    config: &ConfigFile,
+) -> anyhow::Result<()> {
+    if config.rules.is_some() && config.profile.is_some() {
+        anyhow::bail!("set either rules or profile, not both");
+    }
+    if args.rules.is_none() && args.profile.is_none() {
+        args.rules.clone_from(&config.rules);
+        args.profile.clone_from(&config.profile);
+    }
+    if args.src.is_empty() {
+        args.src.extend(config.src.clone());
+    }
+    fill(&mut args.dst, &config.dst);
+    fill(&mut args.history, &config.history);
+    fill(&mut args.threads, &config.threads);
+    for (key, value, config) in [
+        ("min_size", &mut args.min_size, &config.min_size),
+        ("max_size", &mut args.max_size, &config.max_size),
+        ("max_rate", &mut args.max_rate, &config.max_rate),
+    ] {
+        if let (None, Some(size)) = (&value, config) {
+            *value = Some(parse_size(size).map_err(|e| anyhow::anyhow!("{}: {}", key, e))?);
+        }
+    }
+    for (key, value, config) in [
+        ("newer_than", &mut args.newer_than, &config.newer_than),
+        ("older_than", &mut args.older_than, &config.older_than),
+        ("min_age", &mut args.min_age, &config.min_age),
+    ] {
+        if let (None, Some(age)) = (&value, config) {
+            *value = Some(parse_age(age).map_err(|e| anyhow::anyhow!("{}: {}", key, e))?);
+        }
+    }
+    if !args.no_recursive {
+        fill(&mut args.max_depth, &config.max_depth);
+    }
+    if args.include.is_empty() {
+        args.include = config.include.clone().unwrap_or_default();
+    }
+    if args.exclude.is_empty() {
+        args.exclude = config.exclude.clone().unwrap_or_default();
+    }
+    if args.groups.is_empty() {
+        args.groups = config.groups.clone().unwrap_or_default();
+    }
+    fill(&mut args.partial_ext, &config.partial_extensions);
+    args.dry_run |= config.dry_run.unwrap_or(false);
+    args.include_hidden |= config.include_hidden.unwrap_or(false);
+    args.respect_gitignore |= config.respect_gitignore.unwrap_or(false);
+    args.skip_empty |= config.skip_empty.unwrap_or(false);
+    args.copy |= config.copy.unwrap_or(false);
+    if let (None, Some(mode)) = (&args.link, &config.link) {
+        args.link = Some(value_enum("link", mode)?);
+    }
+    args.verify |= config.verify.unwrap_or(false);
+    args.trash |= config.trash.unwrap_or(false);
+
+    if let (None, Some(template)) = (&args.conflict_template, &config.conflict_template) {
+        let template = parse_conflict_template(template)
+            .map_err(|e| anyhow::anyhow!("conflict_template: {}", e))?;
+        args.conflict_template = Some(template);
+    }
+
+    if let (None, Some(form)) = (&args.normalize_unicode, &config.normalize_unicode) {
+        args.normalize_unicode = Some(value_enum("normalize_unicode", form)?);
+    }
+
+    let conflict_given = args.overwrite || args.interactive || set_by_user(matches, "on_conflict");
+    if !conflict_given {
+        args.overwrite = config.overwrite.unwrap_or(false);
+        if let Some(strategy) = &config.on_conflict {
+            args.on_conflict = value_enum("on_conflict", strategy)?;
+        }
+    }
+    Ok(())
+}
+
+/// True if the argument `id` came from the command line or its env variable
+fn set_by_user(matches: Option<&ArgMatches>, id: &str) -> bool {
+    matches!(
+        matches.and_then(|m| m.value_source(id)),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+}
+
+fn fill<T: Clone>(value: &mut Option<T>, config: &Option<T>) {
+    if value.is_none() {
+        value.clone_from(config);
+    }
+}
+
+fn parse_conflict_template(template: &str) -> Result<String, String> {
+    validate_conflict_template(template).map(|_| template.to_string())
+}
+
+/// `GLOB=KIND` for `--simulate-errors`; the kind defaults to `other`
+#[cfg(feature = "testing")]
+fn parse_simulated_error(value: &str) -> Result<(String, SimulatedFailure), String> {
+    let (pattern, kind) = value.rsplit_once('=').unwrap_or((value, "other"));
+    globset::Glob::new(pattern).map_err(|e| e.to_string())?;
+    Ok((pattern.to_string(), SimulatedFailure::from_str(kind, true)?))
+}
+
+fn value_enum<T: ValueEnum>(key: &str, value: &str) -> anyhow::Result<T> {
+    T::from_str(value, true).map_err(|e| anyhow::anyhow!("{}: {}", key, e))
+}
 
+/// An organize run finished, but some of its files could not be moved
+#[derive(Debug, thiserror::Error)]
+#[error("{0} file(s) could not be organized")]
+pub struct FilesFailed(pub usize);
+
+/// The run was stopped with Ctrl-C; moves made before that are recorded
+#[derive(Debug, thiserror::Error)]
+#[error("Cancelled with Ctrl-C; the files moved so far can be undone")]
+pub struct Cancelled;
+
+pub fn run_cli(args: CliArgs) -> anyhow::Result<()> {
     if args.gui {
         // GUI is launched from main.rs
         return Ok(());
     }
+    // before logging is set up: the script goes to stdout and nothing else may
+    if let Some(Commands::Completions { shell }) = args.command {
+        clap_complete::generate(
+            shell,
+            &mut CliArgs::command(),
+            env!("CARGO_BIN_NAME"),
+            &mut std::io::stdout(),
+        );
+        return Ok(());
+    }
+
+    setup_logging(args.log_file(), &args.log_options())?;
+
+    let history_options = args.history_options();
+    let silent = args.silent;
+    match args.command.unwrap_or_else(default_command) {
+        Commands::Organize(organize) => run_organize(organize, history_options, silent)?,
+
+        Commands::Watch { organize, debounce } => run_watch(organize, debounce, history_options)?,
+
+        Commands::UndoLast { history } => {
+            let organizer = dummy_organizer(history, history_options)?;
+            organizer.undo_last()?;
+        }
+
+        Commands::RedoLast { history } => {
+            let organizer = dummy_organizer(history, history_options)?;
+            organizer.redo_last()?;
+        }
+
+        Commands::UndoAll {
+            dry_run: true,
+            history,
+        } => {
+            // read only, so don't prune or set a corrupt history aside
+            let history_options = HistoryOptions {
+                strict: true,
+                limit: None,
+                max_age: None,
+            };
+            let organizer = dummy_organizer(history, history_options)?;
+            print_undo_plan(&organizer.preview_undo_all()?);
+        }
+
+        Commands::UndoAll { history, .. } => {
+            let organizer = dummy_organizer(history, history_options)?;
+            organizer.undo_all()?;
+        }
 
-    match args.command.unwrap_or(Commands::Organize {
-        src: None,
-        dst: None,
-        dry_run: false,
-        overwrite: false,
-        rules: None,
-    }) {
-        Commands::Organize {
-            src,
-            dst,
-            dry_run,
-            overwrite,
+        Commands::UndoSession { id, history } => {
+            let organizer = dummy_organizer(history, history_options)?;
+            organizer.undo_session(id)?;
+        }
+
+        Commands::UndoFile { path, history } => {
+            let organizer = dummy_organizer(history, history_options)?;
+            organizer.undo_file(&path)?;
+        }
+
+        Commands::UndoRange {
+            since,
+            until,
+            history,
+        } => {
+            let organizer = dummy_organizer(history, history_options)?;
+            match until {
+                Some(until) => organizer.undo_between(since, until)?,
+                None => organizer.undo_since(since)?,
+            }
+        }
+
+        Commands::Rules { command } => run_rules(command)?,
+        Commands::Doctor(organize) => run_doctor(organize, history_options)?,
+        Commands::TestRule {
             rules,
+            profile,
+            name,
+        } => run_test_rule(rules, profile, &name)?,
+
+        Commands::History {
+            command:
+                HistoryCommand::Export {
+                    format,
+                    out,
+                    history,
+                },
         } => {
-            let src = src.unwrap_or_else(select_folder_interactive);
-            let dst = dst.unwrap_or_else(|| src.clone());
+            // strict: a corrupt file must not be set aside by a read-only command
+            let history = history_options.open(history).with_strict(true).load()?;
+            export_history(&history.moves, format, out.as_deref())?;
+        }
 
-            let history_path = PathBuf::from(".smart_organizer/history.json");
-            let log_path = PathBuf::from(".smart_organizer/organizer.log");
+        Commands::Completions { .. } => unreachable!("handled before logging setup"),
 
-            std::fs::create_dir_all(".smart_organizer")?;
-            setup_logging(log_path)?;
+        Commands::Sessions { history } => {
+            let sessions = history_options.open(history).load()?.list_sessions();
+            if sessions.is_empty() {
+                println!("No sessions recorded");
+            }
+            for s in sessions {
+                println!(
+                    "{:>4}  {}  {} file(s)",
+                    s.id,
+                    s.started.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S"),
+                    s.files
+                );
+            }
+        }
+    }
 
-            let rule_engine: Box<dyn RuleEngine> = if let Some(rules_json) = rules {
-                let text = std::fs::read_to_string(rules_json)?;
-                Box::new(serde_json::from_str::<CustomRuleEngine>(&text)?) as _
-            } else {
-                Box::new(ExtensionRuleEngine) as _
-            };
+    Ok(())
+}
+
+fn run_organize(
+    args: OrganizeArgs,
+    history_options: HistoryOptions,
+    silent: bool,
+) -> anyhow::Result<()> {
+    let mut organizer = build_organizer(&args, history_options)?;
+    let cancel = organizer.cancel_handle();
+    cancel_on_ctrl_c(cancel.clone())?;
+
+    let bar = if silent || args.json {
+        ProgressBar::hidden()
+    } else {
+        ProgressBar::new(0).with_style(
+            ProgressStyle::with_template("{bar:40} {pos}/{len} ({percent}%) {wide_msg}")
+                .expect("valid progress template"),
+        )
+    };
+    let bar_cb = bar.clone();
+    organizer.set_progress_callback(Box::new(move |p| {
+        bar_cb.set_length(p.total as u64);
+        bar_cb.set_position(p.processed as u64);
+        let message = match p.copied {
+            Some((copied, size)) => format!(
+                "{} ({} / {})",
+                p.current_path.display(),
+                format_size(copied),
+                format_size(size)
+            ),
+            None => p.current_path.display().to_string(),
+        };
+        bar_cb.set_message(message);
+    }));
+    if args.interactive {
+        let bar_prompt = bar.clone();
+        organizer.set_conflict_prompt(Box::new(move |from, to| {
+            bar_prompt.suspend(|| prompt_conflict(from, to))
+        }));
+    }
+
+    let summary = match organizer.organize() {
+        Ok(summary) => summary,
+        Err(e) => {
+            if args.notify {
+                notify_failed(&e.to_string());
+            }
+            return Err(e.into());
+        }
+    };
+    bar.finish_and_clear();
+    if args.notify {
+        notify_finished(&summary, args.dry_run);
+    }
+    if let Some(report) = args.report {
+        write_report(&report, &summary.moves)?;
+        info!("Report written to {:?}", report);
+    }
+    if let Some(path) = args.summary_json {
+        std::fs::write(&path, serde_json::to_string_pretty(&summary)?)?;
+        info!("Summary written to {:?}", path);
+    }
+
+    let errors = organizer.errors();
+    if args.json {
+        println!("{}", serde_json::to_string(&summary)?);
+    } else if !silent {
+        if args.dry_run {
+            print_plan(&summary.moves);
+        }
+        print_summary(&summary);
+        for (path, e) in errors.iter() {
+            println!("  {}: {}", path.display(), e);
+        }
+    }
+    if cancel.load(Ordering::Relaxed) {
+        return Err(Cancelled.into());
+    }
+    if !errors.is_empty() {
+        return Err(FilesFailed(errors.len()).into());
+    }
+    Ok(())
+}
 
-            info!("Source:      {:?}", src);
-            info!("Destination: {:?}", dst);
-            info!("Dry‑run:     {}", dry_run);
-            info!("Overwrite:   {}", overwrite);
-
-            let organizer = Organizer::new(
-                OrganizerConfig {
-                    src_dir: src,
-                    dst_dir: dst,
-                    dry_run,
-                    overwrite,
+/// The first Ctrl-C lets the files in progress finish and the history be
+/// written before the run stops; a second one quits at once
+fn cancel_on_ctrl_c(cancel: Arc<AtomicBool>) -> anyhow::Result<()> {
+    ctrlc::set_handler(move || {
+        if cancel.swap(true, Ordering::Relaxed) {
+            std::process::exit(130);
+        }
+        eprintln!("Stopping after the files in progress (copies are cut short); press Ctrl-C again to quit now");
+    })?;
+    Ok(())
+}
+
+fn run_watch(
+    args: OrganizeArgs,
+    debounce: u64,
+    history_options: HistoryOptions,
+) -> anyhow::Result<()> {
+    if args.report.is_some()
+        || args.summary_json.is_some()
+        || args.json
+        || args.interactive
+        || args.notify
+        || args.max_files.is_some()
+    {
+        anyhow::bail!(
+            "--report, --summary-json, --json, --interactive, --notify and --max-files are not supported in watch mode"
+        );
+    }
+    let organizer = build_organizer(&args, history_options)?;
+    cancel_on_ctrl_c(organizer.cancel_handle())?;
+    watch(&organizer, Duration::from_millis(debounce))?;
+    Ok(())
+}
+
+fn run_rules(command: RulesCommand) -> anyhow::Result<()> {
+    match command {
+        RulesCommand::Save { name, file } => {
+            let (path, config) = save_profile(&name, &file)?;
+            for warning in config.warnings() {
+                warn!("{:?}: {}", file, warning);
+            }
+            println!("Saved profile {:?} to {}", name, path.display());
+        }
+        RulesCommand::List => {
+            let names = list_profiles()?;
+            if names.is_empty() {
+                println!("No rules profiles saved");
+            }
+            for name in names {
+                println!("{}", name);
+            }
+        }
+        RulesCommand::Show { name } => {
+            print!("{}", std::fs::read_to_string(profile_path(&name)?)?);
+        }
+    }
+    Ok(())
+}
+
+/// Classifies `name` as organize would; an existing file is read for its
+/// size, date and type, anything else is classified by its name alone
+fn run_test_rule(
+    rules: Option<PathBuf>,
+    profile: Option<String>,
+    name: &Path,
+) -> anyhow::Result<()> {
+    let rules_path = match (rules, profile) {
+        (Some(path), _) => Some(path),
+        (None, Some(profile)) => Some(profile_path(&profile)?),
+        (None, None) => None,
+    };
+    let config = match &rules_path {
+        Some(path) => {
+            let config = RulesConfig::from_json(&std::fs::read_to_string(path)?)?;
+            for warning in config.warnings() {
+                warn!("{:?}: {}", path, warning);
+            }
+            config
+        }
+        None => RulesConfig::Extension(ExtensionRuleEngine::default()),
+    };
+    let meta = if name.is_file() {
+        FileMeta::from_path(name)?
+    } else {
+        FileMeta::for_name(name)
+    };
+
+    let provenance = match &config {
+        RulesConfig::Custom(engine) => Some(match engine.matching_rule(meta.relative_path()) {
+            Some(rule) => {
+                let index = engine
+                    .rules
+                    .iter()
+                    .position(|r| std::ptr::eq(r, rule))
+                    .unwrap_or_default();
+                format!(
+                    "rule #{}: {:?} {:?}, priority {}",
+                    index + 1,
+                    rule.match_kind,
+                    rule.pattern,
+                    rule.priority
+                )
+            }
+            None => "no rule matched, fallback".to_string(),
+        }),
+        _ => None,
+    };
+    let Classification { root, subdir, rule } =
+        config.into_engine().classify_target(name, &meta)?;
+    let provenance = provenance.or(rule);
+    let target = match root {
+        Some(root) => root.join(&subdir),
+        None => PathBuf::from(&subdir),
+    };
+    match provenance {
+        Some(provenance) => println!("{}  ({})", target.display(), provenance),
+        None => println!("{}", target.display()),
+    }
+    Ok(())
+}
+
+/// Result of one `doctor` check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Health {
+    Ok,
+    Warn,
+    Fail,
+}
+
+/// Checks what `organize` with the same flags would need, without moving
+/// anything: sources readable, destination and history writable, rules valid
+fn run_doctor(args: OrganizeArgs, history_options: HistoryOptions) -> anyhow::Result<()> {
+    let mut checks: Vec<(Health, String, String)> = Vec::new();
+    let mut check =
+        |health, what: &str, detail: String| checks.push((health, what.to_string(), detail));
+
+    if args.src.is_empty() {
+        check(Health::Fail, "Source", "no --src given".into());
+    }
+    for src in &args.src {
+        let what = format!("Source {}", src.display());
+        match std::fs::read_dir(src) {
+            Ok(entries) => check(
+                Health::Ok,
+                &what,
+                format!("readable, {} entries", entries.count()),
+            ),
+            Err(e) => check(Health::Fail, &what, e.to_string()),
+        }
+    }
+
+    if let Some(dst) = args.dst.as_ref().or(args.src.first()) {
+        let what = format!("Destination {}", dst.display());
+        if dst.is_dir() {
+            match probe_writable(dst) {
+                Ok(()) => check(Health::Ok, &what, "writable".into()),
+                Err(e) => check(Health::Fail, &what, format!("not writable: {}", e)),
+            }
+        } else if dst.exists() {
+            check(Health::Fail, &what, "exists but is not a folder".into());
+        } else {
+            match dst.ancestors().skip(1).find(|dir| dir.is_dir()) {
+                Some(parent) => match probe_writable(parent) {
+                    Ok(()) => check(Health::Warn, &what, "missing; will be created".into()),
+                    Err(e) => check(
+                        Health::Fail,
+                        &what,
+                        format!("missing, and {} is not writable: {}", parent.display(), e),
+                    ),
                 },
-                rule_engine,
-                HistoryManager::new(history_path),
+                None => check(Health::Fail, &what, "missing".into()),
+            }
+        }
+        if args.src.iter().any(|src| src == dst) {
+            check(
+                Health::Warn,
+                &what,
+                "same as the source; files are sorted into its subfolders".into(),
             );
+        }
+    }
 
-            organizer.organize()?;
+    let history = args.history.clone().unwrap_or_else(default_history);
+    let what = format!("History {}", history.display());
+    if history.exists() {
+        let writable = std::fs::OpenOptions::new().append(true).open(&history);
+        match (
+            history_options
+                .open(history.clone())
+                .with_strict(true)
+                .load(),
+            writable,
+        ) {
+            (Ok(recorded), Ok(_)) => check(
+                Health::Ok,
+                &what,
+                format!("writable, {} move(s) recorded", recorded.moves.len()),
+            ),
+            (Err(e), _) => check(Health::Fail, &what, format!("unreadable: {}", e)),
+            (_, Err(e)) => check(Health::Fail, &what, format!("not writable: {}", e)),
+        }
+    } else {
+        let parent = history
+            .parent()
+            .filter(|p| !p.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        match parent
+            .ancestors()
+            .find(|dir| dir.is_dir())
+            .map(probe_writable)
+        {
+            Some(Ok(())) => check(Health::Ok, &what, "not created yet, folder writable".into()),
+            Some(Err(e)) => check(Health::Fail, &what, format!("folder not writable: {}", e)),
+            None => check(Health::Fail, &what, "folder missing".into()),
         }
+    }
 
-        Commands::UndoLast { history } => {
-            let organizer = dummy_organizer(history)?;
-            organizer.undo_last()?;
+    let rules_path = match (&args.rules, &args.profile) {
+        (Some(path), _) => Some(Ok(path.clone())),
+        (None, Some(name)) => Some(profile_path(name)),
+        (None, None) => None,
+    };
+    match rules_path {
+        None => check(Health::Ok, "Rules", "built-in (by extension)".into()),
+        Some(Err(e)) => check(Health::Fail, "Rules", e.to_string()),
+        Some(Ok(path)) => {
+            let what = format!("Rules {}", path.display());
+            let parsed = std::fs::read_to_string(&path)
+                .map_err(anyhow::Error::from)
+                .and_then(|text| Ok(RulesConfig::from_json(&text)?));
+            match parsed {
+                Err(e) => check(Health::Fail, &what, e.to_string()),
+                Ok(config) => {
+                    let warnings = config.warnings();
+                    if warnings.is_empty() {
+                        check(Health::Ok, &what, "valid".into());
+                    }
+                    for warning in warnings {
+                        let health = if args.strict_rules {
+                            Health::Fail
+                        } else {
+                            Health::Warn
+                        };
+                        check(health, &what, warning.to_string());
+                    }
+                }
+            }
         }
+    }
 
-        Commands::UndoAll { history } => {
-            let organizer = dummy_organizer(history)?;
-            organizer.undo_all()?;
+    let width = checks
+        .iter()
+        .map(|(_, what, _)| what.len())
+        .max()
+        .unwrap_or(0);
+    for (health, what, detail) in &checks {
+        let (label, style) = match health {
+            Health::Ok => ("OK", Style::new().green()),
+            Health::Warn => ("WARN", Style::new().yellow()),
+            Health::Fail => ("FAIL", Style::new().red().bold()),
+        };
+        println!(
+            "{:<4}  {:<width$}  {}",
+            label.if_supports_color(Stream::Stdout, |l| l.style(style)),
+            what,
+            detail,
+            width = width
+        );
+    }
+    let failed = checks
+        .iter()
+        .filter(|(health, ..)| *health == Health::Fail)
+        .count();
+    if failed > 0 {
+        anyhow::bail!("{} check(s) failed", failed);
+    }
+    Ok(())
+}
+
+/// Creates and removes a scratch file in `dir`
+fn probe_writable(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(format!(".smart_organizer_doctor_{}", std::process::id()));
+    std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    std::fs::remove_file(&probe)
+}
+
+/// Builds the organizer shared by `organize` and `watch`
+fn build_organizer(
+    args: &OrganizeArgs,
+    history_options: HistoryOptions,
+) -> anyhow::Result<Organizer<Box<dyn RuleEngine>>> {
+    let mut sources = args.src.clone();
+    if sources.is_empty() {
+        sources.push(select_folder_interactive());
+    }
+    // the history is shared by runs from every folder, so it records absolute paths
+    let mut sources = sources
+        .iter()
+        .map(std::path::absolute)
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let src = sources.remove(0);
+    let dst = match &args.dst {
+        Some(dst) => std::path::absolute(dst)?,
+        None => src.clone(),
+    };
+
+    let history_path = args.history.clone().unwrap_or_else(default_history);
+
+    let rules_path = match (&args.rules, &args.profile) {
+        (Some(path), _) => Some(path.clone()),
+        (None, Some(name)) => Some(profile_path(name)?),
+        (None, None) => None,
+    };
+    let rule_engine: Box<dyn RuleEngine> = if let Some(rules_json) = &rules_path {
+        let text = std::fs::read_to_string(rules_json)?;
+        let config = RulesConfig::from_json(&text)?;
+        let warnings = config.warnings();
+        for warning in &warnings {
+            warn!("{:?}: {}", rules_json, warning);
+        }
+        if args.strict_rules && !warnings.is_empty() {
+            anyhow::bail!(
+                "{} rule warning(s) in {:?}; refusing to run with --strict-rules",
+                warnings.len(),
+                rules_json
+            );
+        }
+        config.into_engine()
+    } else if !args.groups.is_empty() {
+        let engine = GroupRuleEngine::default();
+        let known: Vec<&str> = engine
+            .groups
+            .keys()
+            .map(String::as_str)
+            .chain([engine.fallback.as_str()])
+            .collect();
+        if let Some(unknown) = args
+            .groups
+            .iter()
+            .find(|g| !known.iter().any(|k| k.eq_ignore_ascii_case(g)))
+        {
+            anyhow::bail!(
+                "Unknown group {:?}; known groups: {}",
+                unknown,
+                known.join(", ")
+            );
+        }
+        Box::new(engine)
+    } else {
+        Box::new(ExtensionRuleEngine::default()) as _
+    };
+
+    let max_depth = if args.no_recursive {
+        Some(1)
+    } else {
+        args.max_depth
+    };
+
+    if let (Some(min), Some(max)) = (args.min_size, args.max_size) {
+        if min > max {
+            anyhow::bail!(
+                "--min-size ({}) is larger than --max-size ({})",
+                format_size(min),
+                format_size(max)
+            );
+        }
+    }
+    info!("Source:      {:?}", src);
+    for extra in &sources {
+        info!("             {:?}", extra);
+    }
+    info!("Destination: {:?}", dst);
+    info!("Dry‑run:     {}", args.dry_run);
+    let on_conflict = if args.overwrite {
+        ConflictStrategy::Overwrite
+    } else {
+        args.on_conflict
+    };
+    info!("On conflict: {:?}", on_conflict);
+
+    Ok(Organizer::new(
+        OrganizerConfig {
+            src_dir: src,
+            extra_src_dirs: sources,
+            dst_dir: dst,
+            dry_run: args.dry_run,
+            on_conflict,
+            conflict_template: args.conflict_template.clone(),
+            threads: args.threads,
+            max_rate: args.max_rate,
+            max_files: args.max_files,
+            max_depth,
+            include_hidden: args.include_hidden,
+            include: args.include.clone(),
+            exclude: args.exclude.clone(),
+            respect_gitignore: args.respect_gitignore,
+            min_size: args.min_size,
+            max_size: args.max_size,
+            skip_empty: args.skip_empty,
+            newer_than: args.newer_than,
+            older_than: args.older_than,
+            partial_extensions: args
+                .partial_ext
+                .as_ref()
+                .map(|list| list.iter().filter(|ext| !ext.is_empty()).cloned().collect()),
+            min_age: args.min_age,
+            only_categories: args.groups.clone(),
+            preserve_tree: args.preserve_tree,
+            bucket_by_initial: args.bucket_initial,
+            merge_existing: args.merge_existing,
+            copy_only: args.copy,
+            link_mode: args.link,
+            no_preserve_times: args.no_preserve_times,
+            on_invalid_name: args.on_invalid_name,
+            treat_dirs: args.treat_dirs,
+            normalize_unicode: args.normalize_unicode,
+            wait_for_lock: args.wait_lock,
+            retry_locked: args.retry_locked,
+            verify: args.verify,
+            use_trash: args.trash,
+            transactional: args.transactional,
+            remove_empty_dirs: args.cleanup,
+            case_insensitive: None,
+            #[cfg(feature = "testing")]
+            simulate_errors: args.simulate_errors.clone(),
+        },
+        rule_engine,
+        history_options.open(history_path),
+    ))
+}
+
+fn print_summary(summary: &OrganizeReport) {
+    if !summary.per_category.is_empty() {
+        let width = summary
+            .per_category
+            .keys()
+            .map(|c| c.chars().count())
+            .max()
+            .unwrap_or(0)
+            .max("Category".len());
+        println!("{:<width$}  {:>6}", "Category", "Files", width = width);
+        for (category, count) in &summary.per_category {
+            println!("{:<width$}  {:>6}", category, count, width = width);
+        }
+    }
+    println!(
+        "{} file(s), {} in {:.2}s; {} conflict(s), {} skipped, {} failed",
+        summary.files_moved,
+        format_size(summary.bytes_moved),
+        summary.elapsed.as_secs_f64(),
+        summary.conflicts,
+        summary.skipped,
+        summary.failed
+    );
+    if summary.in_use > 0 {
+        println!("{} file(s) skipped (in use)", summary.in_use);
+    }
+    if summary.no_space > 0 {
+        println!(
+            "{} file(s) skipped (not enough space at the destination)",
+            summary.no_space
+        );
+    }
+    if summary.already_organized > 0 {
+        println!("{} file(s) already organized", summary.already_organized);
+    }
+    if summary.dirs_removed > 0 {
+        println!("Removed {} empty folder(s)", summary.dirs_removed);
+    }
+    if summary.limit_reached {
+        println!("Stopped at the --max-files limit; the remaining files were left in place");
+    }
+}
+
+/// Prints the dry-run plan grouped by what each move would do to the destination
+fn print_plan(moves: &[PlannedMove]) {
+    let mut groups: BTreeMap<PlanAction, Vec<&PlannedMove>> = BTreeMap::new();
+    for mov in moves {
+        groups.entry(mov.action).or_default().push(mov);
+    }
+    for (action, moves) in groups {
+        let (marker, style) = match action {
+            PlanAction::Created => ('+', Style::new().green()),
+            PlanAction::Renamed => ('>', Style::new().yellow()),
+            PlanAction::Overwritten => ('!', Style::new().red().bold()),
+            PlanAction::Skipped => ('-', Style::new().dimmed()),
+            PlanAction::Conflict => ('?', Style::new().magenta()),
+        };
+        let header = format!("{:?} ({})", action, moves.len());
+        println!(
+            "{}",
+            header.if_supports_color(Stream::Stdout, |h| h.style(style.bold()))
+        );
+        for mov in moves {
+            let mut line = format!(
+                "  {} {} -> {}",
+                marker,
+                mov.from.display(),
+                mov.to.display()
+            );
+            if let Some(rule) = &mov.rule {
+                line.push_str(&format!("  ({})", rule));
+            }
+            println!(
+                "{}",
+                line.if_supports_color(Stream::Stdout, |l| l.style(style))
+            );
+        }
+    }
+}
+
+fn print_undo_plan(actions: &[UndoAction]) {
+    if actions.is_empty() {
+        println!("Nothing to undo");
+        return;
+    }
+    let mut skipped = 0;
+    for action in actions {
+        let mov = &action.moved;
+        let (line, style) = if !action.destination_exists {
+            skipped += 1;
+            let line = format!("  skip    {} (missing)", mov.to.display());
+            (line, Style::new().dimmed())
+        } else if mov.kind == TransferKind::Copy {
+            (
+                format!("  delete  {} (copy)", mov.to.display()),
+                Style::new().red(),
+            )
+        } else if matches!(mov.kind, TransferKind::HardLink | TransferKind::Symlink) {
+            (
+                format!("  delete  {} (link)", mov.to.display()),
+                Style::new().red(),
+            )
+        } else if action.origin_occupied {
+            let line = format!(
+                "  restore {} -> {} (taken, a numbered name is used)",
+                mov.to.display(),
+                mov.from.display()
+            );
+            (line, Style::new().yellow())
+        } else {
+            let line = format!("  restore {} -> {}", mov.to.display(), mov.from.display());
+            (line, Style::new().green())
+        };
+        println!(
+            "{}",
+            line.if_supports_color(Stream::Stdout, |l| l.style(style))
+        );
+    }
+    println!(
+        "{} to undo, {} skipped; nothing was changed",
+        actions.len() - skipped,
+        skipped
+    );
+}
+
+/// Writes the move list as CSV if the path ends in `.csv`, JSON otherwise
+fn write_report(path: &Path, moves: &[PlannedMove]) -> anyhow::Result<()> {
+    let is_csv = path
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("csv"));
+    if is_csv {
+        let mut writer = csv::Writer::from_path(path)?;
+        for mov in moves {
+            writer.serialize(mov)?;
         }
+        writer.flush()?;
+    } else {
+        std::fs::write(path, serde_json::to_string_pretty(moves)?)?;
     }
+    Ok(())
+}
+
+/// One line of `history export`
+#[derive(Serialize)]
+struct ExportRow<'a> {
+    from: &'a Path,
+    to: &'a Path,
+    /// RFC 3339 in UTC, whole seconds
+    time: String,
+    session: u64,
+    /// Rule pattern or engine that chose the folder; empty for old entries
+    rule: &'a str,
+}
 
+fn export_history(
+    moves: &[MovedFile],
+    format: ExportFormat,
+    out: Option<&Path>,
+) -> anyhow::Result<()> {
+    use std::io::Write;
+
+    let rows = moves.iter().map(|m| ExportRow {
+        from: &m.from,
+        to: &m.to,
+        time: m.time.to_rfc3339_opts(SecondsFormat::Secs, true),
+        session: m.session_id,
+        rule: m.rule_matched.as_deref().unwrap_or_default(),
+    });
+    let writer: Box<dyn std::io::Write> = match out {
+        Some(path) => Box::new(std::fs::File::create(path)?),
+        None => Box::new(std::io::stdout().lock()),
+    };
+    match format {
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for row in rows {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+        ExportFormat::Json => {
+            let mut writer = std::io::BufWriter::new(writer);
+            serde_json::to_writer_pretty(&mut writer, &rows.collect::<Vec<_>>())?;
+            writeln!(writer)?;
+            writer.flush()?;
+        }
+    }
+    if let Some(path) = out {
+        info!("Exported {} move(s) to {:?}", moves.len(), path);
+    }
     Ok(())
 }
 
-/// Returns an Organizer with default settings for undo commands
+/// Accepts RFC 3339 timestamps or plain `YYYY-MM-DD` dates (local midnight)
+fn parse_time(s: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(t) = DateTime::parse_from_rfc3339(s) {
+        return Ok(t.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(s, "%Y-%m-%d")
+        .map_err(|_| format!("expected RFC 3339 or YYYY-MM-DD, got {:?}", s))?;
+    date.and_hms_opt(0, 0, 0)
+        .and_then(|t| t.and_local_timezone(Local).earliest())
+        .map(|t| t.with_timezone(&Utc))
+        .ok_or_else(|| format!("{} has no local midnight", s))
+}
+
+/// Organizer used by the undo commands, which only need the history
 fn dummy_organizer(
     history: PathBuf,
+    history_options: HistoryOptions,
 ) -> anyhow::Result<Organizer<Box<dyn RuleEngine>>> {
     let src = std::env::current_dir()?;
     let dst = src.clone();
@@ -130,14 +1500,38 @@ fn dummy_organizer(
         OrganizerConfig {
             src_dir: src,
             dst_dir: dst,
-            dry_run: false,
-            overwrite: false,
+            // nothing is moved into dst, so don't probe it
+            case_insensitive: Some(false),
+            ..Default::default()
         },
-        Box::new(ExtensionRuleEngine) as Box<dyn RuleEngine>,
-        HistoryManager::new(history),
+        Box::new(ExtensionRuleEngine::default()) as Box<dyn RuleEngine>,
+        history_options.open(history),
     ))
 }
 
+/// Asks what to do with a file whose target name is already taken
+fn prompt_conflict(from: &Path, to: &Path) -> ConflictChoice {
+    const CHOICES: [(&str, ConflictChoice); 5] = [
+        ("Rename", ConflictChoice::Rename),
+        ("Overwrite", ConflictChoice::Overwrite),
+        ("Skip", ConflictChoice::Skip),
+        ("Overwrite all", ConflictChoice::OverwriteAll),
+        ("Skip all", ConflictChoice::SkipAll),
+    ];
+    let items: Vec<&str> = CHOICES.iter().map(|(label, _)| *label).collect();
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!(
+            "{} already exists (moving {})",
+            to.display(),
+            from.display()
+        ))
+        .items(&items)
+        .default(0)
+        .interact()
+        .unwrap_or(0);
+    CHOICES[selection].1
+}
+
 /// Simple interactive folder selection menu
 fn select_folder_interactive() -> PathBuf {
     let theme = ColorfulTheme::default();
@@ -1,18 +1,29 @@
 // src/ui_gui.rs
 
-use crate::{
-    history::HistoryManager,
-    organizer::{Organizer, OrganizerConfig},
-    rules::{ExtensionRuleEngine, RuleEngine},
-};
-use crossbeam_channel::{bounded, Receiver};
+use crate::desktop_notify::{notify_failed, notify_finished};
+use crate::workspace::default_history;
+use clap::ValueEnum;
+use crossbeam_channel::{unbounded, Receiver, Sender};
 use eframe::{App, Frame};
 use egui::{Context, RichText};
-use log::{error, info};
+use log::error;
 use parking_lot::Mutex;
 use rfd::FileDialog;
+use serde::{Deserialize, Serialize};
+use smart_file_organizer::{
+    errors::OrganizerError,
+    history::HistoryManager,
+    logger::{setup_logging_with_sink, LogOptions},
+    organizer::{ConflictStrategy, OrganizeReport, Organizer, OrganizerConfig, Progress},
+    rules::{
+        CustomRule, CustomRuleEngine, ExtensionRuleEngine, MatchKind, MatchMode, RuleEngine,
+        RulesConfig,
+    },
+    units::format_size,
+};
 use std::{
-    path::PathBuf,
+    collections::VecDeque,
+    path::{Path, PathBuf},
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -21,43 +32,580 @@ use std::{
     time::Duration,
 };
 
-pub fn run_gui() -> eframe::Result<()> {
+/// Older lines are dropped from the log pane (organizer.log keeps everything)
+const MAX_LOG_LINES: usize = 2000;
+
+const MATCH_KINDS: [MatchKind; 4] = [
+    MatchKind::Extension,
+    MatchKind::Regex,
+    MatchKind::Glob,
+    MatchKind::PathComponent,
+];
+const MATCH_MODES: [MatchMode; 2] = [MatchMode::FirstMatch, MatchMode::MostSpecific];
+
+/// Key of `GuiSettings` in eframe's storage
+const SETTINGS_KEY: &str = "settings";
+
+pub fn run_gui(log_options: LogOptions, log_file: PathBuf) -> eframe::Result<()> {
+    let (tx, rx) = unbounded();
+    let log_tx = tx.clone();
+    if let Err(e) = setup_logging_with_sink(log_file, &log_options, move |line| {
+        let _ = log_tx.send(GuiEvent::Log(line));
+    }) {
+        eprintln!("Cannot set up logging: {}", e);
+    }
+
     let native_options = eframe::NativeOptions::default();
     eframe::run_native(
         "Smart File Organizer",
         native_options,
-        Box::new(|_cc| Box::new(GuiApp::default())),
+        Box::new(|cc| {
+            let mut app = GuiApp::new(tx, rx);
+            if let Some(storage) = cc.storage {
+                app.restore(storage);
+            }
+            Box::new(app)
+        }),
     )
 }
 
+/// Messages from the logger and the worker thread to the UI
+enum GuiEvent {
+    Log(String),
+    Progress(Progress),
+    Finished {
+        report: Option<OrganizeReport>,
+        error: Option<String>,
+        failures: Vec<(PathBuf, String)>,
+    },
+}
+
 struct GuiApp {
     src: Option<PathBuf>,
     dst: Option<PathBuf>,
     running: bool,
-    progress: Arc<AtomicBool>,
-    last_error: Arc<Mutex<Option<String>>>,
-    receiver: Option<Receiver<()>>,
-    overwrite: bool,
+    counts: Option<Progress>,
+    /// Cancel flag of the organizer currently running in the worker thread
+    cancel: Arc<Mutex<Option<Arc<AtomicBool>>>>,
+    /// Pause flag of that organizer
+    pause: Arc<Mutex<Option<Arc<AtomicBool>>>>,
+    last_error: Option<String>,
+    failures: Vec<(PathBuf, String)>,
+    /// Statistics and moves of the last finished run
+    report: Option<OrganizeReport>,
+    /// The last run was a dry run (or preview): `report` lists planned moves
+    report_is_plan: bool,
+    log: VecDeque<String>,
+    sender: Sender<GuiEvent>,
+    receiver: Receiver<GuiEvent>,
+    on_conflict: ConflictStrategy,
     dry_run: bool,
+    /// Show a desktop notification when a run ends
+    notify: bool,
+    rules: RulesEditor,
+    /// What happened to the last items dropped onto the window
+    drop_message: Option<String>,
 }
 
-impl Default for GuiApp {
+/// Choices restored on the next launch; eframe keeps them in the app's
+/// data folder and saves them periodically and on exit
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct GuiSettings {
+    src: Option<PathBuf>,
+    dst: Option<PathBuf>,
+    dry_run: bool,
+    notify: bool,
+    /// As spelled for `--on-conflict`
+    on_conflict: String,
+    rules_enabled: bool,
+    /// The rules panel as rules.json text
+    rules: Option<String>,
+}
+
+/// One row of the rules panel; turned into a `CustomRule` on every use so
+/// that edited patterns are compiled afresh
+#[derive(Debug, Clone, Default)]
+struct RuleRow {
+    pattern: String,
+    match_kind: MatchKind,
+    target_dir: String,
+    priority: i32,
+    /// Not editable here, kept so that saving a loaded rules.json doesn't drop it
+    target_root: Option<PathBuf>,
+}
+
+/// Custom rules edited in the side panel
+struct RulesEditor {
+    /// Organize with these rules instead of sorting by extension
+    enabled: bool,
+    rows: Vec<RuleRow>,
+    fallback: String,
+    /// Folder for unmatched files without an extension; empty uses `fallback`
+    no_extension: String,
+    match_mode: MatchMode,
+    /// Result of the last load/save, or why it failed
+    message: Option<String>,
+}
+
+impl Default for RulesEditor {
     fn default() -> Self {
+        Self {
+            enabled: false,
+            rows: vec![RuleRow::default()],
+            fallback: "Other".to_string(),
+            no_extension: String::new(),
+            match_mode: MatchMode::default(),
+            message: None,
+        }
+    }
+}
+
+impl RulesEditor {
+    fn engine(&self) -> CustomRuleEngine {
+        CustomRuleEngine {
+            rules: self
+                .rows
+                .iter()
+                .map(|row| {
+                    CustomRule::new(
+                        row.pattern.clone(),
+                        row.match_kind,
+                        row.target_dir.clone(),
+                        row.priority,
+                    )
+                    .with_target_root(row.target_root.clone())
+                })
+                .collect(),
+            fallback: self.fallback.clone(),
+            no_extension: Some(self.no_extension.trim())
+                .filter(|dir| !dir.is_empty())
+                .map(str::to_string),
+            match_mode: self.match_mode,
+        }
+    }
+
+    fn load(&mut self, path: &Path) -> Result<(), OrganizerError> {
+        let text = std::fs::read_to_string(path).map_err(|e| OrganizerError::io_at(path, e))?;
+        self.load_json(&text)
+    }
+
+    fn load_json(&mut self, text: &str) -> Result<(), OrganizerError> {
+        let RulesConfig::Custom(engine) = RulesConfig::from_json(text)? else {
+            return Err(OrganizerError::Other(
+                "Only custom rules (no \"engine\" or \"engine\": \"custom\") can be edited here"
+                    .into(),
+            ));
+        };
+        self.rows = engine
+            .rules
+            .into_iter()
+            .map(|rule| RuleRow {
+                pattern: rule.pattern,
+                match_kind: rule.match_kind,
+                target_dir: rule.target_dir,
+                priority: rule.priority,
+                target_root: rule.target_root,
+            })
+            .collect();
+        self.fallback = engine.fallback;
+        self.no_extension = engine.no_extension.unwrap_or_default();
+        self.match_mode = engine.match_mode;
+        Ok(())
+    }
+
+    fn save(&self, path: &Path) -> Result<(), OrganizerError> {
+        std::fs::write(path, self.to_json()?).map_err(|e| OrganizerError::io_at(path, e))
+    }
+
+    fn to_json(&self) -> Result<String, OrganizerError> {
+        Ok(serde_json::to_string_pretty(&RulesConfig::Custom(
+            self.engine(),
+        ))?)
+    }
+
+    /// Draws the panel; returns true when "Preview" was clicked
+    fn show(&mut self, ui: &mut egui::Ui, idle: bool) -> bool {
+        ui.heading("Rules");
+        ui.checkbox(&mut self.enabled, "Organize with these rules");
+
+        let warnings = self.engine().validate();
+        let mut remove = None;
+        egui::Grid::new("rules").striped(true).show(ui, |ui| {
+            ui.label("Pattern");
+            ui.label("Kind");
+            ui.label("Target folder");
+            ui.label("Priority");
+            ui.end_row();
+            for (i, row) in self.rows.iter_mut().enumerate() {
+                ui.add(egui::TextEdit::singleline(&mut row.pattern).desired_width(120.0));
+                egui::ComboBox::from_id_source(("match_kind", i))
+                    .selected_text(format!("{:?}", row.match_kind))
+                    .show_ui(ui, |ui| {
+                        for kind in MATCH_KINDS {
+                            ui.selectable_value(&mut row.match_kind, kind, format!("{:?}", kind));
+                        }
+                    });
+                ui.add(egui::TextEdit::singleline(&mut row.target_dir).desired_width(100.0));
+                ui.add(egui::DragValue::new(&mut row.priority));
+                if ui.small_button("✖").on_hover_text("Remove rule").clicked() {
+                    remove = Some(i);
+                }
+                ui.end_row();
+                for warning in warnings.iter().filter(|w| w.index == i) {
+                    ui.colored_label(
+                        egui::Color32::from_rgb(230, 140, 0),
+                        format!("⚠ {}", warning.reason),
+                    );
+                    ui.end_row();
+                }
+            }
+        });
+        if let Some(i) = remove {
+            self.rows.remove(i);
+        }
+        if ui.button("Add rule").clicked() {
+            self.rows.push(RuleRow::default());
+        }
+
+        ui.horizontal(|ui| {
+            ui.label("Fallback folder");
+            ui.text_edit_singleline(&mut self.fallback);
+        });
+        ui.horizontal(|ui| {
+            ui.label("No extension");
+            ui.add(
+                egui::TextEdit::singleline(&mut self.no_extension).hint_text("same as fallback"),
+            );
+        });
+        egui::ComboBox::from_label("When several rules match")
+            .selected_text(format!("{:?}", self.match_mode))
+            .show_ui(ui, |ui| {
+                for mode in MATCH_MODES {
+                    ui.selectable_value(&mut self.match_mode, mode, format!("{:?}", mode));
+                }
+            });
+
+        ui.horizontal(|ui| {
+            if ui.button("Load JSON…").clicked() {
+                if let Some(path) = FileDialog::new().add_filter("JSON", &["json"]).pick_file() {
+                    self.message = Some(match self.load(&path) {
+                        Ok(()) => format!("Loaded {}", path.display()),
+                        Err(e) => describe(&e),
+                    });
+                }
+            }
+            if ui.button("Save JSON…").clicked() {
+                if let Some(path) = FileDialog::new()
+                    .add_filter("JSON", &["json"])
+                    .set_file_name("rules.json")
+                    .save_file()
+                {
+                    self.message = Some(match self.save(&path) {
+                        Ok(()) => format!("Saved {}", path.display()),
+                        Err(e) => describe(&e),
+                    });
+                }
+            }
+        });
+        if let Some(message) = &self.message {
+            ui.label(message);
+        }
+
+        ui.separator();
+        ui.add_enabled(idle, egui::Button::new("Preview"))
+            .on_hover_text("Dry run over the source folder with these rules")
+            .clicked()
+    }
+}
+
+impl GuiApp {
+    fn new(sender: Sender<GuiEvent>, receiver: Receiver<GuiEvent>) -> Self {
         Self {
             src: None,
             dst: None,
             running: false,
-            progress: Arc::new(AtomicBool::new(false)),
-            last_error: Arc::new(Mutex::new(None)),
-            receiver: None,
-            overwrite: false,
+            counts: None,
+            cancel: Arc::new(Mutex::new(None)),
+            pause: Arc::new(Mutex::new(None)),
+            last_error: None,
+            failures: Vec::new(),
+            report: None,
+            report_is_plan: false,
+            log: VecDeque::new(),
+            sender,
+            receiver,
+            on_conflict: ConflictStrategy::default(),
             dry_run: false,
+            notify: false,
+            rules: RulesEditor::default(),
+            drop_message: None,
+        }
+    }
+
+    /// Applies the settings saved by the last session; unreadable parts are left at their defaults
+    fn restore(&mut self, storage: &dyn eframe::Storage) {
+        let Some(settings) = eframe::get_value::<GuiSettings>(storage, SETTINGS_KEY) else {
+            return;
+        };
+        self.src = settings.src;
+        self.dst = settings.dst;
+        self.dry_run = settings.dry_run;
+        self.notify = settings.notify;
+        if let Ok(on_conflict) = ConflictStrategy::from_str(&settings.on_conflict, true) {
+            self.on_conflict = on_conflict;
+        }
+        self.rules.enabled = settings.rules_enabled;
+        if let Some(rules) = settings.rules {
+            if let Err(e) = self.rules.load_json(&rules) {
+                error!(
+                    "Cannot restore the rules of the last session: {}",
+                    describe(&e)
+                );
+            }
+        }
+    }
+
+    fn settings(&self) -> GuiSettings {
+        GuiSettings {
+            src: self.src.clone(),
+            dst: self.dst.clone(),
+            dry_run: self.dry_run,
+            notify: self.notify,
+            on_conflict: self
+                .on_conflict
+                .to_possible_value()
+                .map(|value| value.get_name().to_string())
+                .unwrap_or_default(),
+            rules_enabled: self.rules.enabled,
+            rules: self.rules.to_json().ok(),
         }
     }
+
+    /// A folder dropped onto the window becomes the source, or the
+    /// destination while Shift is held; anything else is ignored
+    fn handle_drops(&mut self, ctx: &Context) {
+        let (dropped, to_destination) =
+            ctx.input(|i| (i.raw.dropped_files.clone(), i.modifiers.shift));
+        if dropped.is_empty() {
+            return;
+        }
+        let (folders, others): (Vec<_>, Vec<_>) = dropped
+            .into_iter()
+            .filter_map(|file| file.path)
+            .partition(|path| path.is_dir());
+        let mut notes = Vec::new();
+        if let Some(folder) = folders.first() {
+            let field = if to_destination {
+                "Destination"
+            } else {
+                "Source"
+            };
+            notes.push(format!("{} set to {}", field, folder.display()));
+            if to_destination {
+                self.dst = Some(folder.clone());
+            } else {
+                self.src = Some(folder.clone());
+            }
+        }
+        if folders.len() > 1 {
+            notes.push(format!(
+                "{} more folder(s) ignored, drop one at a time",
+                folders.len() - 1
+            ));
+        }
+        if !others.is_empty() {
+            notes.push(format!(
+                "{} item(s) ignored, only folders can be dropped",
+                others.len()
+            ));
+        }
+        if notes.is_empty() {
+            notes.push("Nothing usable was dropped".into());
+        }
+        self.drop_message = Some(notes.join("; "));
+    }
+
+    /// Dims the window and tells what a drop would do while files are dragged over it
+    fn paint_drop_hint(&self, ctx: &Context) {
+        let (hovering, to_destination) =
+            ctx.input(|i| (!i.raw.hovered_files.is_empty(), i.modifiers.shift));
+        if !hovering {
+            return;
+        }
+        let text = if to_destination {
+            "Drop a folder to use it as the destination"
+        } else {
+            "Drop a folder to use it as the source\n(hold Shift for the destination)"
+        };
+        let painter = ctx.layer_painter(egui::LayerId::new(
+            egui::Order::Foreground,
+            egui::Id::new("drop_hint"),
+        ));
+        let screen = ctx.screen_rect();
+        painter.rect_filled(screen, 0.0, egui::Color32::from_black_alpha(160));
+        painter.text(
+            screen.center(),
+            egui::Align2::CENTER_CENTER,
+            text,
+            egui::TextStyle::Heading.resolve(&ctx.style()),
+            egui::Color32::WHITE,
+        );
+    }
+
+    fn drain_events(&mut self) {
+        while let Ok(event) = self.receiver.try_recv() {
+            match event {
+                GuiEvent::Log(line) => {
+                    if self.log.len() == MAX_LOG_LINES {
+                        self.log.pop_front();
+                    }
+                    self.log.push_back(line);
+                }
+                GuiEvent::Progress(p) => self.counts = Some(p),
+                GuiEvent::Finished {
+                    report,
+                    error,
+                    failures,
+                } => {
+                    self.report = report;
+                    self.last_error = error;
+                    self.failures = failures;
+                    self.running = false;
+                }
+            }
+        }
+    }
+
+    /// Whether the running job was paused with the Pause button
+    fn paused(&self) -> bool {
+        self.pause
+            .lock()
+            .as_ref()
+            .is_some_and(|p| p.load(Ordering::Relaxed))
+    }
+
+    /// `preview` forces a dry run with the rules from the side panel
+    fn start(&mut self, src: PathBuf, preview: bool) {
+        // the history is shared by runs from every folder, so keep it free of relative paths
+        let src = std::path::absolute(&src).unwrap_or(src);
+        let dst = self.dst.clone().unwrap_or_else(|| src.clone());
+        let dst = std::path::absolute(&dst).unwrap_or(dst);
+        let dry_run = self.dry_run || preview;
+        let on_conflict = self.on_conflict;
+        let notify = self.notify;
+        let rules: Box<dyn RuleEngine> = if self.rules.enabled || preview {
+            Box::new(self.rules.engine())
+        } else {
+            Box::new(ExtensionRuleEngine::default())
+        };
+
+        let tx = self.sender.clone();
+        let cancel = self.cancel.clone();
+        let pause = self.pause.clone();
+        self.counts = None;
+        self.last_error = None;
+        self.failures.clear();
+        self.report = None;
+        self.report_is_plan = dry_run;
+        self.running = true;
+
+        thread::spawn(move || {
+            let history_path = default_history();
+
+            let mut organizer = Organizer::new(
+                OrganizerConfig {
+                    src_dir: src,
+                    dst_dir: dst,
+                    dry_run,
+                    on_conflict,
+                    ..Default::default()
+                },
+                rules,
+                // strict: a dry run must not even set a corrupt history aside
+                HistoryManager::new(history_path).with_strict(dry_run),
+            );
+            let progress_tx = tx.clone();
+            organizer.set_progress_callback(Box::new(move |p| {
+                let _ = progress_tx.send(GuiEvent::Progress(p));
+            }));
+            *cancel.lock() = Some(organizer.cancel_handle());
+            *pause.lock() = Some(organizer.pause_handle());
+
+            let (report, error) = match organizer.organize() {
+                Ok(summary) => (Some(summary), None),
+                Err(e) => {
+                    error!("Organize error: {}", e);
+                    (None, Some(describe(&e)))
+                }
+            };
+            if notify {
+                match (&report, &error) {
+                    (Some(report), _) => notify_finished(report, dry_run),
+                    (None, Some(error)) => notify_failed(error),
+                    (None, None) => {}
+                }
+            }
+            let failures = organizer
+                .errors()
+                .iter()
+                .map(|(path, e)| (path.clone(), describe(e)))
+                .collect();
+
+            *cancel.lock() = None;
+            *pause.lock() = None;
+            let _ = tx.send(GuiEvent::Finished {
+                report,
+                error,
+                failures,
+            });
+        });
+    }
 }
 
 impl App for GuiApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, SETTINGS_KEY, &self.settings());
+    }
+
     fn update(&mut self, ctx: &Context, _frame: &mut Frame) {
+        self.drain_events();
+        self.handle_drops(ctx);
+
+        egui::TopBottomPanel::bottom("log")
+            .resizable(true)
+            .default_height(150.0)
+            .show(ctx, |ui| {
+                ui.label(RichText::new("Log").strong());
+                let row_height = ui.text_style_height(&egui::TextStyle::Monospace);
+                egui::ScrollArea::vertical()
+                    .id_source("log")
+                    .auto_shrink([false; 2])
+                    .stick_to_bottom(true)
+                    .show_rows(ui, row_height, self.log.len(), |ui, rows| {
+                        for line in self.log.range(rows) {
+                            ui.monospace(line);
+                        }
+                    });
+            });
+
+        egui::SidePanel::right("rules")
+            .resizable(true)
+            .show(ctx, |ui| {
+                egui::ScrollArea::vertical()
+                    .id_source("rules")
+                    .show(ui, |ui| {
+                        if self.rules.show(ui, !self.running) {
+                            match self.src.clone() {
+                                Some(src) => self.start(src, true),
+                                None => {
+                                    self.rules.message = Some("Select a source folder first".into())
+                                }
+                            }
+                        }
+                    });
+            });
+
         egui::CentralPanel::default().show(ctx, |ui| {
             ui.heading("Smart File Organizer (Rust + egui)");
 
@@ -86,74 +634,190 @@ impl App for GuiApp {
                     .map_or("—".to_string(), |p| p.to_string_lossy().to_string());
                 ui.label(dst_label);
             });
+            if let Some(message) = &self.drop_message {
+                ui.label(RichText::new(message).italics());
+            }
 
             ui.checkbox(&mut self.dry_run, "Dry-run mode");
-            ui.checkbox(&mut self.overwrite, "Overwrite conflicting files");
+            ui.checkbox(&mut self.notify, "Notify when done");
+            egui::ComboBox::from_label("On conflict")
+                .selected_text(format!("{:?}", self.on_conflict))
+                .show_ui(ui, |ui| {
+                    for &strategy in ConflictStrategy::value_variants() {
+                        ui.selectable_value(
+                            &mut self.on_conflict,
+                            strategy,
+                            format!("{:?}", strategy),
+                        );
+                    }
+                });
 
             ui.separator();
 
             if !self.running {
                 if ui.button("Start").clicked() {
                     if let Some(src) = self.src.clone() {
-                        let dst = self.dst.clone().unwrap_or_else(|| src.clone());
-                        let dry_run = self.dry_run;
-                        let overwrite = self.overwrite;
-
-                        let (tx, rx) = bounded(1);
-                        self.receiver = Some(rx);
-
-                        let progress = self.progress.clone();
-                        let last_error = self.last_error.clone();
-                        self.running = true;
-
-                        thread::spawn(move || {
-                            progress.store(true, Ordering::Relaxed);
-
-                            let history_path = PathBuf::from(".smart_organizer/history.json");
-                            std::fs::create_dir_all(".smart_organizer").ok();
-
-                            let organizer = Organizer::new(
-                                OrganizerConfig {
-                                    src_dir: src,
-                                    dst_dir: dst,
-                                    dry_run,
-                                    overwrite,
-                                },
-                                ExtensionRuleEngine,
-                                HistoryManager::new(history_path),
-                            );
-
-                            if let Err(e) = organizer.organize() {
-                                error!("Organize error: {}", e);
-                                *last_error.lock() = Some(e.to_string());
-                            }
-
-                            progress.store(false, Ordering::Relaxed);
-                            let _ = tx.send(());
-                        });
+                        self.start(src, false);
                     }
                 }
-            } else if ui.button("Cancel").clicked() {
-                self.progress.store(false, Ordering::Relaxed);
-            }
-
-            if let Some(rx) = &self.receiver {
-                if rx.try_recv().is_ok() {
-                    self.running = false;
-                    self.receiver = None;
+            } else {
+                if ui.button("Cancel").clicked() {
+                    // moves finished so far stay in history and can be undone
+                    if let Some(cancel) = self.cancel.lock().as_ref() {
+                        cancel.store(true, Ordering::Relaxed);
+                    }
+                }
+                // the files being moved right now still finish
+                let paused = self.paused();
+                if ui.button(if paused { "Resume" } else { "Pause" }).clicked() {
+                    if let Some(pause) = self.pause.lock().as_ref() {
+                        pause.store(!paused, Ordering::Relaxed);
+                    }
                 }
             }
 
-            if self.running && self.progress.load(Ordering::Relaxed) {
-                ui.label(RichText::new("Working…").italics());
+            let cancelling = self
+                .cancel
+                .lock()
+                .as_ref()
+                .is_some_and(|c| c.load(Ordering::Relaxed));
+            if self.running && cancelling {
+                ui.label(RichText::new("Cancelling…").italics());
                 ctx.request_repaint_after(Duration::from_millis(200));
             } else if self.running {
-                ui.label(RichText::new("Done").strong());
+                let status = if self.paused() {
+                    "Paused"
+                } else {
+                    "Working…"
+                };
+                ui.label(RichText::new(status).italics());
+                if let Some(p) = &self.counts {
+                    let fraction = if p.total == 0 {
+                        1.0
+                    } else {
+                        p.processed as f32 / p.total as f32
+                    };
+                    ui.add(egui::ProgressBar::new(fraction).text(format!(
+                        "{}/{} ({:.0}%)",
+                        p.processed,
+                        p.total,
+                        fraction * 100.0
+                    )));
+                    ui.label(p.current_path.to_string_lossy());
+                    if let Some((copied, size)) = p.copied {
+                        ui.label(format!(
+                            "Copying: {} of {}",
+                            format_size(copied),
+                            format_size(size)
+                        ));
+                    }
+                }
+                ctx.request_repaint_after(Duration::from_millis(200));
+            } else if let Some(report) = &self.report {
+                let done = if self.report_is_plan {
+                    "Dry run done, nothing was moved"
+                } else {
+                    "Done"
+                };
+                ui.label(RichText::new(done).strong());
+                ui.label(format!(
+                    "{} file(s), {} in {:.2}s; {} conflict(s), {} skipped, {} failed",
+                    report.files_moved,
+                    format_size(report.bytes_moved),
+                    report.elapsed.as_secs_f64(),
+                    report.conflicts,
+                    report.skipped,
+                    report.failed
+                ));
+                if report.in_use > 0 {
+                    ui.label(format!("{} file(s) skipped (in use)", report.in_use));
+                }
+                if report.no_space > 0 {
+                    ui.label(format!(
+                        "{} file(s) skipped (not enough space at the destination)",
+                        report.no_space
+                    ));
+                }
+                if report.already_organized > 0 {
+                    ui.label(format!(
+                        "{} file(s) already organized",
+                        report.already_organized
+                    ));
+                }
+                egui::Grid::new("summary").striped(true).show(ui, |ui| {
+                    for (category, count) in &report.per_category {
+                        ui.label(category);
+                        ui.label(count.to_string());
+                        ui.end_row();
+                    }
+                });
+
+                if !report.moves.is_empty() {
+                    ui.separator();
+                    ui.label(if self.report_is_plan {
+                        "Planned moves:"
+                    } else {
+                        "Files of the last run:"
+                    });
+                    let row_height = ui.text_style_height(&egui::TextStyle::Body);
+                    egui::ScrollArea::vertical()
+                        .id_source("moves")
+                        .max_height(200.0)
+                        .show_rows(ui, row_height, report.moves.len(), |ui, rows| {
+                            for mov in &report.moves[rows] {
+                                ui.horizontal(|ui| {
+                                    ui.label(RichText::new(format!("{:?}", mov.action)).strong());
+                                    ui.label(format!(
+                                        "{} → {}",
+                                        mov.from.display(),
+                                        mov.to.display()
+                                    ));
+                                });
+                            }
+                        });
+                }
             }
 
-            if let Some(err) = self.last_error.lock().clone() {
+            if let Some(err) = &self.last_error {
                 ui.colored_label(egui::Color32::RED, format!("Last error: {}", err));
             }
+
+            if !self.failures.is_empty() {
+                ui.separator();
+                ui.colored_label(
+                    egui::Color32::RED,
+                    format!("{} file(s) failed:", self.failures.len()),
+                );
+                egui::ScrollArea::vertical()
+                    .id_source("failures")
+                    .max_height(150.0)
+                    .show(ui, |ui| {
+                        for (path, err) in &self.failures {
+                            ui.label(format!("{}: {}", path.display(), err));
+                        }
+                    });
+            }
         });
+
+        self.paint_drop_hint(ctx);
+    }
+}
+
+/// Error text for the UI, with a hint for permission problems
+fn describe(e: &OrganizerError) -> String {
+    let permission = match e {
+        OrganizerError::PermissionDenied { .. } => true,
+        OrganizerError::TargetDir { source, .. } => {
+            source.kind() == std::io::ErrorKind::PermissionDenied
+        }
+        _ => false,
+    };
+    if permission {
+        format!(
+            "{} — choose a folder you can write to or run the app with elevated permissions",
+            e
+        )
+    } else {
+        e.to_string()
     }
 }
@@ -0,0 +1,147 @@
+use crate::{
+    errors::OrganizerError,
+    organizer::{Organizer, PlanAction},
+    rules::RuleEngine,
+};
+use crossbeam_channel::{unbounded, RecvTimeoutError};
+use log::{info, warn};
+use notify::{EventKind, RecursiveMode, Watcher};
+use std::{
+    collections::HashMap,
+    fs,
+    path::PathBuf,
+    sync::atomic::Ordering,
+    time::{Duration, Instant},
+};
+use walkdir::WalkDir;
+
+/// How often pending files are re-checked while no events arrive
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// A file seen by the watcher that is not yet known to be fully written
+struct Pending {
+    size: Option<u64>,
+    since: Instant,
+}
+
+/// Organizes files as they appear in the source folders until the organizer
+/// is cancelled. A file is processed once its size stayed unchanged for `debounce`.
+pub fn watch<R: RuleEngine + 'static>(
+    organizer: &Organizer<R>,
+    debounce: Duration,
+) -> Result<(), OrganizerError> {
+    let (tx, rx) = unbounded();
+    let mut watcher = notify::recommended_watcher(tx).map_err(watch_error)?;
+    // Events carry absolute paths; map them back onto each source folder for the organizer
+    let mut roots = Vec::new();
+    for src_dir in organizer.config().src_dirs() {
+        watcher
+            .watch(src_dir, RecursiveMode::Recursive)
+            .map_err(watch_error)?;
+        info!(
+            "Watching {:?} (debounce {} ms)",
+            src_dir,
+            debounce.as_millis()
+        );
+        roots.push((fs::canonicalize(src_dir)?, src_dir.to_path_buf()));
+    }
+    // the innermost root first, should sources be nested
+    roots.sort_by_key(|(root, _)| std::cmp::Reverse(root.components().count()));
+
+    let cancel = organizer.cancel_handle();
+    let mut pending: HashMap<PathBuf, Pending> = HashMap::new();
+    // Files we just put in place ourselves; their events are ignored for a while
+    let mut placed: HashMap<PathBuf, Instant> = HashMap::new();
+
+    while !cancel.load(Ordering::Relaxed) {
+        match rx.recv_timeout(POLL_INTERVAL) {
+            Ok(Ok(event)) => {
+                if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) {
+                    for path in event.paths {
+                        let Some((src_dir, rel)) = roots.iter().find_map(|(root, src_dir)| {
+                            Some((src_dir, path.strip_prefix(root).ok()?))
+                        }) else {
+                            continue;
+                        };
+                        for file in expand(src_dir.join(rel)) {
+                            if !placed.contains_key(&file) {
+                                pending.insert(
+                                    file,
+                                    Pending {
+                                        size: None,
+                                        since: Instant::now(),
+                                    },
+                                );
+                            }
+                        }
+                    }
+                }
+            }
+            Ok(Err(e)) => warn!("Watch error: {}", e),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        placed.retain(|_, at| at.elapsed() < debounce + POLL_INTERVAL);
+        let ready = take_settled(&mut pending, debounce);
+        if ready.is_empty() {
+            continue;
+        }
+
+        let report = organizer.organize_paths(&ready)?;
+        for (path, e) in organizer.errors().iter() {
+            warn!("Failed to organize {:?}: {}", path, e);
+        }
+        let now = Instant::now();
+        placed.extend(
+            report
+                .moves
+                .into_iter()
+                .filter(|m| m.action != PlanAction::Skipped)
+                .map(|m| (m.to, now)),
+        );
+    }
+
+    info!("Watch stopped");
+    Ok(())
+}
+
+/// A directory moved into the watched tree produces a single event,
+/// so its files are picked up here
+fn expand(path: PathBuf) -> Vec<PathBuf> {
+    if !path.is_dir() {
+        return vec![path];
+    }
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.into_path())
+        .collect()
+}
+
+/// Removes and returns the pending files whose size has been stable for `debounce`.
+/// Files that disappeared are dropped.
+fn take_settled(pending: &mut HashMap<PathBuf, Pending>, debounce: Duration) -> Vec<PathBuf> {
+    let mut ready = Vec::new();
+    pending.retain(|path, state| {
+        let Ok(meta) = fs::metadata(path) else {
+            return false;
+        };
+        if state.size != Some(meta.len()) {
+            state.size = Some(meta.len());
+            state.since = Instant::now();
+            return true;
+        }
+        if state.since.elapsed() >= debounce {
+            ready.push(path.clone());
+            return false;
+        }
+        true
+    });
+    ready
+}
+
+fn watch_error(e: notify::Error) -> OrganizerError {
+    OrganizerError::Other(format!("Watch error: {}", e))
+}
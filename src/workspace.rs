@@ -0,0 +1,187 @@
+// src/workspace.rs
+//! Per-user folder for the history, its lock and the log file
+//! (`~/.local/share/smart-organizer` on Linux), shared by runs from any folder.
+//! Older versions kept them in `.smart_organizer` in the current folder.
+
+use directories::ProjectDirs;
+use smart_file_organizer::{HistoryManager, OrganizerError};
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Folder used before the data folder, relative to the current folder
+const LEGACY_DIR: &str = ".smart_organizer";
+
+/// The data folder; `.smart_organizer` if the OS has no home folder to put it in
+pub fn workspace_dir() -> PathBuf {
+    ProjectDirs::from("", "", "smart-organizer")
+        .map(|dirs| dirs.data_dir().to_path_buf())
+        .unwrap_or_else(|| PathBuf::from(LEGACY_DIR))
+}
+
+/// History file used when neither `--history` nor the env variable is set
+pub fn default_history() -> PathBuf {
+    workspace_dir().join("history.json")
+}
+
+/// Log file used when `--log-file` is not given
+pub fn default_log_file() -> PathBuf {
+    workspace_dir().join("organizer.log")
+}
+
+/// Moves the history (and everything else but the lock) of a `.smart_organizer`
+/// in the current folder into the data folder, unless that has a history
+/// already. Relative paths in the history are made absolute against the
+/// current folder, since the data folder is shared by runs from any folder.
+/// Names the data folder has are left behind, as are files that can't be
+/// moved; both only print a notice, they never stop the run.
+pub fn migrate_legacy_dir() {
+    let legacy = Path::new(LEGACY_DIR);
+    let workspace = workspace_dir();
+    if !legacy.join("history.json").is_file() || workspace.join("history.json").exists() {
+        return;
+    }
+    let same = match (fs::canonicalize(legacy), fs::canonicalize(&workspace)) {
+        (Ok(legacy), Ok(workspace)) => legacy == workspace,
+        _ => workspace == legacy,
+    };
+    if same {
+        return;
+    }
+    let cwd = match std::env::current_dir() {
+        Ok(cwd) => cwd,
+        Err(e) => {
+            eprintln!("Cannot move {}: {}", legacy.display(), e);
+            return;
+        }
+    };
+    match move_entries(legacy, &workspace, &cwd) {
+        Ok(left) if left.is_empty() => {
+            let _ = fs::remove_dir(legacy);
+            eprintln!(
+                "Moved {} to {}; history and logs are kept there from now on",
+                legacy.display(),
+                workspace.display()
+            );
+        }
+        Ok(left) => eprintln!(
+            "Moved the history in {} to {}; left behind: {}",
+            legacy.display(),
+            workspace.display(),
+            left.join(", ")
+        ),
+        Err(e) => eprintln!(
+            "Cannot move {} to {}: {}; pass --history {}/history.json to use it",
+            legacy.display(),
+            workspace.display(),
+            e,
+            LEGACY_DIR
+        ),
+    }
+}
+
+/// Moves the files of `from` into `to`, the history through `migrate_history`;
+/// returns the names it left behind
+fn move_entries(from: &Path, to: &Path, base: &Path) -> io::Result<Vec<String>> {
+    fs::create_dir_all(to)?;
+    let mut left = Vec::new();
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let target = to.join(&name);
+        if name == "lock" {
+            // only guards runs using this history; a new one is made next to it
+            let _ = fs::remove_file(entry.path());
+            continue;
+        }
+        let moved = if target.exists() {
+            false
+        } else if name == "history.json" {
+            migrate_history(&entry.path(), &target, base).is_ok()
+        } else {
+            move_file(&entry.path(), &target).is_ok()
+        };
+        if !moved {
+            left.push(name.to_string_lossy().into_owned());
+        }
+    }
+    Ok(left)
+}
+
+/// Writes the history at `from` to `to` with relative paths resolved against
+/// `base`, then deletes `from`
+fn migrate_history(from: &Path, to: &Path, base: &Path) -> Result<(), OrganizerError> {
+    let mut history = HistoryManager::new(from.to_path_buf())
+        .with_strict(true)
+        .load()?;
+    let resolve = |path: &mut PathBuf| -> io::Result<()> {
+        if path.is_relative() {
+            *path = std::path::absolute(base.join(&*path))?;
+        }
+        Ok(())
+    };
+    for mov in history.moves.iter_mut().chain(history.redo.iter_mut()) {
+        resolve(&mut mov.from)?;
+        resolve(&mut mov.to)?;
+    }
+    for dir in &mut history.removed_dirs {
+        resolve(&mut dir.path)?;
+    }
+    HistoryManager::new(to.to_path_buf()).save(&history)?;
+    fs::remove_file(from)?;
+    Ok(())
+}
+
+/// Rename, or copy and delete when the data folder is on another filesystem
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use smart_file_organizer::history::{History, MovedFile, TransferKind};
+
+    #[test]
+    fn migrated_history_has_absolute_paths() {
+        let dir = tempfile::tempdir().unwrap();
+        let (legacy, workspace) = (dir.path().join(LEGACY_DIR), dir.path().join("data"));
+        fs::create_dir_all(&legacy).unwrap();
+        let history = History {
+            moves: vec![MovedFile {
+                from: PathBuf::from("./in/a.txt"),
+                to: PathBuf::from("out/txt/a.txt"),
+                time: Utc::now(),
+                session_id: 1,
+                kind: TransferKind::Move,
+                replaced_trashed: false,
+                rule_matched: None,
+            }],
+            ..Default::default()
+        };
+        HistoryManager::new(legacy.join("history.json"))
+            .save(&history)
+            .unwrap();
+        fs::write(legacy.join("organizer.log"), "log").unwrap();
+
+        let base = dir.path().join("cwd");
+        assert!(move_entries(&legacy, &workspace, &base).unwrap().is_empty());
+
+        let migrated = HistoryManager::new(workspace.join("history.json"))
+            .load()
+            .unwrap();
+        assert_eq!(migrated.moves[0].from, base.join("in/a.txt"));
+        assert_eq!(migrated.moves[0].to, base.join("out/txt/a.txt"));
+        assert!(!legacy.join("history.json").exists());
+        assert_eq!(
+            fs::read_to_string(workspace.join("organizer.log")).unwrap(),
+            "log"
+        );
+    }
+}